@@ -0,0 +1,329 @@
+use crate::balance::{target_for, Target};
+use crate::moment::{self, Duration, Moment, STime};
+use crate::reader::{Clock, SrcLoc};
+use chrono::naive::NaiveDate;
+use chrono::Datelike;
+use serde::Serialize;
+use std::fmt::{self, Display, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AbsenceKind {
+    Vacation,
+    Sick,
+    Holiday,
+}
+
+impl FromStr for AbsenceKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "vacation" => Ok(AbsenceKind::Vacation),
+            "sick" => Ok(AbsenceKind::Sick),
+            "holiday" => Ok(AbsenceKind::Holiday),
+            _ => Err(anyhow::anyhow!(
+                "Unknown absence '{}', expected vacation, sick or holiday",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for AbsenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            AbsenceKind::Vacation => "vacation",
+            AbsenceKind::Sick => "sick",
+            AbsenceKind::Holiday => "holiday",
+        };
+        f.pad(s)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Absence {
+    pub date: NaiveDate,
+    pub kind: AbsenceKind,
+    pub half: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip)]
+    pub loc: SrcLoc,
+}
+
+/// Absences are equal if they describe the same time off, wherever they were read from
+impl PartialEq for Absence {
+    fn eq(&self, b: &Self) -> bool {
+        (self.date, self.kind, self.half, &self.note) == (b.date, b.kind, b.half, &b.note)
+    }
+}
+
+impl Absence {
+    pub fn halves(&self) -> u32 {
+        match self.half {
+            true => 1,
+            false => 2,
+        }
+    }
+
+    pub fn credit(&self, targets: &[Target]) -> Duration {
+        let t = target_for(targets, self.date);
+        match self.half {
+            true => Duration::new(0, t.mins() / 2),
+            false => t,
+        }
+    }
+
+    /// A clock from midnight for the time credited, with "(kind)" as its job
+    pub fn as_clock(&self, credit: Duration) -> Clock {
+        let c_in = Moment::new(self.date, STime::new(0, 0));
+        Clock {
+            c_in,
+            c_out: c_in.checked_add(credit).unwrap_or(c_in),
+            job: format!("({})", self.kind),
+            tags: Vec::new(),
+            note: self.note.clone(),
+            loc: self.loc.clone(),
+            out_loc: self.loc.clone(),
+        }
+    }
+}
+
+pub fn credited_clocks(absences: &[Absence], targets: &[Target]) -> Vec<Clock> {
+    absences
+        .iter()
+        .filter_map(|a| match a.credit(targets) {
+            c if c.mins() > 0 => Some(a.as_clock(c)),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn read_holiday_list(s: &str) -> anyhow::Result<Vec<(NaiveDate, String)>> {
+    let mut res = Vec::new();
+    for (n, l) in s.lines().enumerate() {
+        let l = l.trim();
+        if l.is_empty() || l.starts_with('#') {
+            continue;
+        }
+        let (d, name) = match l.find(|c: char| c == ',' || c.is_whitespace()) {
+            Some(i) => (&l[..i], l[i + 1..].trim()),
+            None => (l, ""),
+        };
+        let date = NaiveDate::parse_from_str(d, "%Y-%m-%d")
+            .or_else(|_| moment::date_from_str(d, None))
+            .map_err(|_| {
+                anyhow::anyhow!("Line {} of the holiday list has no date: '{}'", n + 1, l)
+            })?;
+        res.push((date, name.trim_matches('"').to_string()));
+    }
+    Ok(res)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allowance {
+    pub kind: AbsenceKind,
+    pub halves: i64,
+    pub carry_max: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaveRow {
+    pub kind: AbsenceKind,
+    pub allowance: Option<i64>,
+    pub carried: i64,
+    pub taken: i64,
+    pub booked: i64,
+    pub remaining: Option<i64>,
+}
+
+/// The leave for the year, per type. Days up to `today` are taken, later ones are booked.
+/// Allowance left over at the end of a year is carried into the next, up to its limit.
+pub fn leave(
+    absences: &[Absence],
+    allowances: &[Allowance],
+    year: i32,
+    today: NaiveDate,
+) -> Vec<LeaveRow> {
+    let mut kinds: Vec<AbsenceKind> = allowances.iter().map(|a| a.kind).collect();
+    kinds.extend(
+        absences
+            .iter()
+            .filter(|a| a.date.year() == year)
+            .map(|a| a.kind),
+    );
+    kinds.sort();
+    kinds.dedup();
+    kinds
+        .into_iter()
+        .map(|kind| {
+            let used = |f: &dyn Fn(&Absence) -> bool| -> i64 {
+                absences
+                    .iter()
+                    .filter(|a| a.kind == kind && f(a))
+                    .map(|a| a.halves() as i64)
+                    .sum()
+            };
+            let taken = used(&|a| a.date.year() == year && a.date <= today);
+            let booked = used(&|a| a.date.year() == year && a.date > today);
+            let allowance = allowances.iter().find(|a| a.kind == kind);
+            let carried = match allowance {
+                Some(al) => {
+                    let first = absences
+                        .iter()
+                        .filter(|a| a.kind == kind)
+                        .map(|a| a.date.year())
+                        .min()
+                        .unwrap_or(year);
+                    (first..year).fold(0, |carry, y| {
+                        let left = al.halves + carry - used(&|a| a.date.year() == y);
+                        let left = left.max(0);
+                        al.carry_max.map_or(left, |m| left.min(m))
+                    })
+                }
+                None => 0,
+            };
+            LeaveRow {
+                kind,
+                allowance: allowance.map(|a| a.halves),
+                carried,
+                taken,
+                booked,
+                remaining: allowance.map(|a| a.halves + carried - taken - booked),
+            }
+        })
+        .collect()
+}
+
+pub fn days_str(halves: i64) -> String {
+    let sign = if halves < 0 { "-" } else { "" };
+    let h = halves.abs();
+    match h % 2 {
+        0 => format!("{}{}", sign, h / 2),
+        _ => format!("{}{}.5", sign, h / 2),
+    }
+}
+
+pub fn halves_from_str(s: &str) -> anyhow::Result<i64> {
+    let d: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("'{}' is not a number of days, as in 25 or 12.5", s))?;
+    let h = d * 2.;
+    match h.fract() == 0. {
+        true => Ok(h as i64),
+        false => Err(anyhow::anyhow!(
+            "'{}' is not a whole or half number of days",
+            s
+        )),
+    }
+}
+
+pub fn render_leave(rows: &[LeaveRow], year: i32) -> String {
+    let opt = |h: Option<i64>| h.map(days_str).unwrap_or_else(|| "-".to_string());
+    let mut res = String::new();
+    writeln!(
+        res,
+        "{:<8}  {:>9}  {:>7}  {:>7}  {:>7}  {:>9}",
+        year, "Allowance", "Carried", "Taken", "Booked", "Remaining"
+    )
+    .ok();
+    for r in rows {
+        writeln!(
+            res,
+            "{:<8}  {:>9}  {:>7}  {:>7}  {:>7}  {:>9}",
+            r.kind,
+            opt(r.allowance),
+            days_str(r.carried),
+            days_str(r.taken),
+            days_str(r.booked),
+            opt(r.remaining)
+        )
+        .ok();
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::ClockStore;
+    #[test]
+    pub fn test_absences_are_credited() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "1/11/2021\n  !vacation\n2/11/2021\n  !sick half\n  work,13:00 -17:00\n6/11/2021\n  !holiday \"Saturday\"\n",
+                "test",
+            )
+            .unwrap();
+        assert_eq!(store.absences.len(), 3);
+        assert_eq!(store.absences[1].kind, AbsenceKind::Sick);
+        assert_eq!(store.absences[2].note.as_deref(), Some("Saturday"));
+        let h = Duration::new(8, 0);
+        let targets = vec![Target {
+            from: None,
+            days: [h, h, h, h, h, Duration::default(), Duration::default()],
+        }];
+        let clocks = credited_clocks(&store.absences, &targets);
+        //No target on a saturday, so nothing to credit
+        assert_eq!(clocks.len(), 2);
+        assert_eq!(clocks[1].job, "(sick)");
+        assert_eq!(clocks[1].duration().unwrap(), Duration::new(4, 0));
+
+        assert!(store.read("2/11/2021\n  !vacation\n", "more").is_err());
+    }
+
+    #[test]
+    pub fn test_leave_carries_over() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "1/11/2021\n  !vacation\n2/11/2021\n  !vacation half\n3/1/2022\n  !vacation\n4/1/2022\n  !vacation\n9/1/2022\n  !vacation\n5/1/2022\n  !sick half\n",
+                "test",
+            )
+            .unwrap();
+        let allowances = vec![Allowance {
+            kind: AbsenceKind::Vacation,
+            halves: 10,
+            carry_max: Some(4),
+        }];
+        let today = NaiveDate::from_ymd(2022, 1, 5);
+        let rows = leave(&store.absences, &allowances, 2022, today);
+        //1.5 days used in 2021 leaves 3.5, but only 2 can be carried
+        assert_eq!(
+            rows[0],
+            LeaveRow {
+                kind: AbsenceKind::Vacation,
+                allowance: Some(10),
+                carried: 4,
+                taken: 4,
+                booked: 2,
+                remaining: Some(8),
+            }
+        );
+        assert_eq!(rows[1].kind, AbsenceKind::Sick);
+        assert_eq!(rows[1].remaining, None);
+        let s = render_leave(&rows, 2022);
+        assert!(s.contains("vacation          5        2        2        1          4"));
+        assert!(s.contains("sick              -        0      0.5        0          -"));
+    }
+
+    #[test]
+    pub fn test_read_holiday_list() {
+        let l =
+            read_holiday_list("# UK\n2026-12-25,Christmas Day\n26/12/2026 Boxing Day\n\n").unwrap();
+        assert_eq!(
+            l,
+            vec![
+                (
+                    NaiveDate::from_ymd(2026, 12, 25),
+                    "Christmas Day".to_string()
+                ),
+                (NaiveDate::from_ymd(2026, 12, 26), "Boxing Day".to_string())
+            ]
+        );
+        assert!(read_holiday_list("Christmas").is_err());
+    }
+}
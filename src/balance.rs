@@ -0,0 +1,281 @@
+use crate::absence::Absence;
+use crate::err::ClockErr;
+use crate::moment::Duration;
+use crate::reader::{Break, Clock};
+use chrono::naive::NaiveDate;
+use chrono::Datelike;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub from: Option<NaiveDate>,
+    pub days: [Duration; 7],
+}
+
+/// The latest target that has started wins, no target at all expects nothing
+pub fn target_for(targets: &[Target], d: NaiveDate) -> Duration {
+    targets
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.from.map(|f| f <= d).unwrap_or(true))
+        .max_by_key(|(i, t)| (t.from, *i))
+        .map(|(_, t)| t.days[d.weekday().num_days_from_monday() as usize])
+        .unwrap_or_default()
+}
+
+/// A bare number is hours, as in "7.5", otherwise a duration as in "7:30"
+pub fn hours_from_str(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    match s.parse::<f64>() {
+        Ok(h) if !h.is_finite() => Err(anyhow::anyhow!("'{}' is not a number of hours", s)),
+        Ok(h) => Duration::checked_new(0, (h * 60.).round() as i64)
+            .ok_or_else(|| anyhow::anyhow!("'{}' is too many hours", s)),
+        Err(_) => s.parse(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            _ => Err(anyhow::anyhow!(
+                "Cannot show balance per '{}', expected day, week or month",
+                s
+            )),
+        }
+    }
+}
+
+impl Period {
+    pub fn key(&self, d: NaiveDate) -> String {
+        match self {
+            Period::Day => d.format("%Y-%m-%d %a").to_string(),
+            Period::Week => d.format("%G-W%V").to_string(),
+            Period::Month => d.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub key: String,
+    pub worked: Duration,
+    pub target: Duration,
+    pub absent: Duration,
+    pub breaks: Duration,
+    pub balance: Duration,
+}
+
+/// Absences up to `end` are credited as time worked.
+pub fn balance(
+    clocks: &[Clock],
+    absences: &[Absence],
+    breaks: &[&Break],
+    targets: &[Target],
+    per: Period,
+    end: NaiveDate,
+) -> Result<Vec<Row>, ClockErr> {
+    let mut worked: BTreeMap<NaiveDate, Duration> = BTreeMap::new();
+    for c in clocks {
+        for (d, t) in c.day_parts()? {
+            *worked.entry(d).or_default() += t;
+        }
+    }
+    let mut absent: BTreeMap<NaiveDate, Duration> = BTreeMap::new();
+    for a in absences.iter().filter(|a| a.date <= end) {
+        *absent.entry(a.date).or_default() += a.credit(targets);
+    }
+    let mut paused: BTreeMap<NaiveDate, Duration> = BTreeMap::new();
+    for b in breaks {
+        *paused.entry(b.start.d).or_default() += b.duration();
+    }
+    let start = match worked.keys().chain(absent.keys()).min() {
+        Some(s) => *s,
+        None => return Ok(Vec::new()),
+    };
+    let end = worked.keys().next_back().map_or(end, |l| end.max(*l));
+
+    let mut res: Vec<Row> = Vec::new();
+    let mut bal = Duration::default();
+    for d in start.iter_days().take_while(|d| *d <= end) {
+        let w = worked.get(&d).copied().unwrap_or_default();
+        let a = absent.get(&d).copied().unwrap_or_default();
+        let b = paused.get(&d).copied().unwrap_or_default();
+        let t = target_for(targets, d);
+        bal += w + a - t;
+        let key = per.key(d);
+        match res.last_mut() {
+            Some(r) if r.key == key => {
+                r.worked += w;
+                r.absent += a;
+                r.breaks += b;
+                r.target += t;
+                r.balance = bal;
+            }
+            _ => res.push(Row {
+                key,
+                worked: w,
+                target: t,
+                absent: a,
+                breaks: b,
+                balance: bal,
+            }),
+        }
+    }
+    Ok(res)
+}
+
+pub fn render_balance(rows: &[Row]) -> String {
+    let width = rows
+        .iter()
+        .map(|r| r.key.chars().count())
+        .chain(Some(6))
+        .max()
+        .unwrap_or(6);
+    let mut res = String::new();
+    writeln!(
+        res,
+        "{:<width$}  {:>7}  {:>7}  {:>7}  {:>7}  {:>7}  {:>7}",
+        "Period",
+        "Worked",
+        "Breaks",
+        "Absent",
+        "Target",
+        "Diff",
+        "Balance",
+        width = width
+    )
+    .ok();
+    for r in rows {
+        writeln!(
+            res,
+            "{:<width$}  {:>7}  {:>7}  {:>7}  {:>7}  {:>7}  {:>7}",
+            r.key,
+            r.worked,
+            r.breaks,
+            r.absent,
+            r.target,
+            r.worked + r.absent - r.target,
+            r.balance,
+            width = width
+        )
+        .ok();
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::ClockStore;
+    #[test]
+    pub fn test_balance_runs_across_target_changes() {
+        let mut store = ClockStore::new();
+        //1/11/2021 is a Monday
+        store
+            .read(
+                "1/11/2021\n  work,9:00 -12:00 ~\n  12:30 -17:30\n2/11/2021\n  work,9:00 -16:00\n5/11/2021\n  !sick half\n8/11/2021\n  work,9:00 -15:00\n",
+                "test",
+            )
+            .unwrap();
+        assert!(hours_from_str("inf").is_err());
+        assert!(hours_from_str("NaN").is_err());
+        assert_eq!(hours_from_str("7h30m").unwrap(), Duration::new(7, 30));
+        let week = |h: &str| {
+            let h = hours_from_str(h).unwrap();
+            [h, h, h, h, h, Duration::default(), Duration::default()]
+        };
+        let targets = vec![
+            Target {
+                from: None,
+                days: week("7.5"),
+            },
+            Target {
+                from: Some(NaiveDate::from_ymd(2021, 11, 8)),
+                days: week("6:00"),
+            },
+        ];
+        let end = NaiveDate::from_ymd(2021, 11, 8);
+        let days = balance(
+            &store.clocks,
+            &store.absences,
+            &store.selected_breaks(),
+            &targets,
+            Period::Day,
+            end,
+        )
+        .unwrap();
+        assert_eq!(days[0].balance, Duration::new(0, 30));
+        assert_eq!(days[0].breaks, Duration::new(0, 30));
+        assert_eq!(days[1].balance, Duration::new(0, 0));
+        //Wed to Fri are not worked but half of Fri is sick, the weekend has no target
+        assert_eq!(days[4].absent, Duration::new(3, 45));
+        assert_eq!(days[6].balance, Duration::new(-18, -45));
+        assert_eq!(days[7].target, Duration::new(6, 0));
+        assert_eq!(days[7].balance, Duration::new(-18, -45));
+
+        let weeks = balance(
+            &store.clocks,
+            &store.absences,
+            &store.selected_breaks(),
+            &targets,
+            Period::Week,
+            end,
+        )
+        .unwrap();
+        assert_eq!(weeks.len(), 2);
+        assert_eq!(weeks[0].worked, Duration::new(15, 0));
+        assert_eq!(weeks[0].target, Duration::new(37, 30));
+        assert!(render_balance(&weeks)
+            .contains("2021-W44    15:00    00:30    03:45    37:30   -18:45   -18:45"));
+    }
+    #[test]
+    pub fn test_balance_starts_with_the_date_filter() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "4/1/2021\n  !vacation\n1/11/2021\n  work,9:00 -17:00\n",
+                "test",
+            )
+            .unwrap();
+        let clap = clap::App::new("test")
+            .arg(
+                clap::Arg::with_name("since")
+                    .long("since")
+                    .takes_value(true),
+            )
+            .get_matches_from(vec!["test", "--since", "2021-11-01"]);
+        let f = crate::filter::get_args_date_filter(&clap).unwrap().unwrap();
+        store.clocks.retain(|c| f(c.c_in.d));
+        store.absences.retain(|a| f(a.date));
+        let h = Duration::new(8, 0);
+        let targets = vec![Target {
+            from: None,
+            days: [h, h, h, h, h, Duration::default(), Duration::default()],
+        }];
+        let rows = balance(
+            &store.clocks,
+            &store.absences,
+            &[],
+            &targets,
+            Period::Month,
+            NaiveDate::from_ymd(2021, 11, 1),
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].key, "2021-11");
+        assert_eq!(rows[0].balance, Duration::default());
+    }
+}
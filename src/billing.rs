@@ -0,0 +1,252 @@
+use crate::err::ClockErr;
+use crate::filter::job_matches;
+use crate::moment::Duration;
+use crate::reader::{Clock, Group};
+use chrono::naive::NaiveDate;
+use derive_more::*;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+use std::str::FromStr;
+
+/// An amount of money in hundredths, so sums do not drift
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Add, AddAssign, Sum)]
+pub struct Money(i64);
+
+impl Money {
+    pub fn cents(&self) -> i64 {
+        self.0
+    }
+
+    pub fn percent(&self, pct: i64) -> Money {
+        let x = self.0 as i128 * pct as i128;
+        Money((x + 5000).div_euclid(10000) as i64)
+    }
+
+    pub fn for_time(&self, d: Duration) -> Money {
+        let x = self.0 as i128 * d.mins() as i128;
+        Money((x + 30).div_euclid(60) as i64)
+    }
+}
+
+impl FromStr for Money {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let bad = || anyhow::anyhow!("'{}' is not an amount of money, expected one like 80.50", s);
+        let (neg, num) = match s.strip_prefix('-') {
+            Some(r) => (true, r),
+            None => (false, s),
+        };
+        let (whole, frac) = match num.split_once('.') {
+            Some((w, f)) => (w, f),
+            None => (num, ""),
+        };
+        let digits = |p: &str| p.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || frac.len() > 2 || !digits(whole) || !digits(frac) {
+            return Err(bad());
+        }
+        let whole: i64 = whole.parse().map_err(|_| bad())?;
+        let frac: i64 = format!("{:0<2}", frac).parse().map_err(|_| bad())?;
+        let c = whole
+            .checked_mul(100)
+            .and_then(|w| w.checked_add(frac))
+            .ok_or_else(bad)?;
+        Ok(Money(if neg { -c } else { c }))
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let c = self.0.unsigned_abs();
+        f.pad(&format!("{}{}.{:02}", sign, c / 100, c % 100))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum RateTarget {
+    Job(String),
+    Group(String),
+}
+
+impl RateTarget {
+    /// A job beats a group, and "client/project" beats "client".
+    pub fn fit(&self, job: &str, groups: &[Group]) -> Option<usize> {
+        match self {
+            RateTarget::Job(j) if job_matches(j, job) => Some(j.split('/').count() + 1),
+            RateTarget::Job(_) => None,
+            RateTarget::Group(g) => groups
+                .iter()
+                .filter(|gr| gr.name == *g)
+                .any(|gr| gr.members.iter().any(|m| job_matches(m, job)))
+                .then_some(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Rate {
+    pub target: RateTarget,
+    pub amount: Money,
+    pub currency: Option<String>,
+    pub from: Option<NaiveDate>,
+}
+
+impl Rate {
+    fn fit(&self, c: &Clock, d: NaiveDate, groups: &[Group]) -> Option<usize> {
+        if self.from.map(|f| f > d).unwrap_or(false) {
+            return None;
+        }
+        self.target.fit(&c.job, groups)
+    }
+}
+
+/// The closest fit wins, then the latest start, then the last defined.
+pub fn rate_for<'a>(
+    rates: &'a [Rate],
+    c: &Clock,
+    d: NaiveDate,
+    groups: &[Group],
+) -> Option<&'a Rate> {
+    rates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| Some(((r.fit(c, d, groups)?, r.from, i), r)))
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, r)| r)
+}
+
+#[derive(Debug, Default)]
+pub struct Earning {
+    pub time: Duration,
+    pub amount: Money,
+}
+
+/// Earnings per job and currency. A rate without a currency has "",
+/// and time with no rate at all is kept under None.
+pub type Earnings = BTreeMap<(String, Option<String>), Earning>;
+
+pub fn earnings(clocks: &[Clock], groups: &[Group], rates: &[Rate]) -> Result<Earnings, ClockErr> {
+    let mut res = Earnings::new();
+    for c in clocks {
+        for (d, t) in c.day_parts()? {
+            let (cur, amount) = match rate_for(rates, c, d, groups) {
+                Some(r) => (
+                    Some(r.currency.clone().unwrap_or_default()),
+                    r.amount.for_time(t),
+                ),
+                None => (None, Money::default()),
+            };
+            let e = res.entry((c.job.clone(), cur)).or_default();
+            e.time += t;
+            e.amount += amount;
+        }
+    }
+    Ok(res)
+}
+
+pub fn render_earnings(e: &Earnings) -> String {
+    let width = e
+        .keys()
+        .map(|(j, _)| j.chars().count())
+        .chain(Some(5))
+        .max()
+        .unwrap_or(5);
+    let mut res = String::new();
+    writeln!(
+        res,
+        "{:<width$}  {:>7}  {:>12}  Currency",
+        "Job",
+        "Time",
+        "Amount",
+        width = width
+    )
+    .ok();
+    let mut totals: BTreeMap<Option<&str>, Earning> = BTreeMap::new();
+    for ((job, cur), v) in e {
+        let (amount, cur_name) = match cur {
+            Some(c) => (v.amount.to_string(), c.as_str()),
+            None => ("-".to_string(), "(no rate)"),
+        };
+        writeln!(
+            res,
+            "{:<width$}  {:>7}  {:>12}  {}",
+            job,
+            v.time,
+            amount,
+            cur_name,
+            width = width
+        )
+        .ok();
+        let t = totals.entry(cur.as_deref()).or_default();
+        t.time += v.time;
+        t.amount += v.amount;
+    }
+    writeln!(res, "{}", "-".repeat(width + 33)).ok();
+    for (cur, v) in totals {
+        let (amount, cur_name) = match cur {
+            Some(c) => (v.amount.to_string(), c),
+            None => ("-".to_string(), "(no rate)"),
+        };
+        writeln!(
+            res,
+            "{:<width$}  {:>7}  {:>12}  {}",
+            "Total",
+            v.time,
+            amount,
+            cur_name,
+            width = width
+        )
+        .ok();
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::ClockStore;
+    #[test]
+    pub fn test_money_parse_and_charge() {
+        assert_eq!("80.5".parse::<Money>().unwrap(), Money(8050));
+        assert_eq!("80".parse::<Money>().unwrap(), Money(8000));
+        assert!("80.505".parse::<Money>().is_err());
+        assert_eq!("-5.50".parse::<Money>().unwrap(), Money(-550));
+        assert_eq!("-0.5".parse::<Money>().unwrap().to_string(), "-0.50");
+        assert!("--5".parse::<Money>().is_err());
+        assert!("5.-5".parse::<Money>().is_err());
+        assert_eq!(
+            Money(8050).for_time(Duration::new(1, 30)).to_string(),
+            "120.75"
+        );
+    }
+
+    #[test]
+    pub fn test_earnings_use_rate_of_the_day() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "$fun[play]\n@$fun 10\n@acme 50 GBP\n1/11/2021\n  acme/web,9:00 -11:00\n  play,11:00 -12:00\n  home,12:00 -13:00\n2/11/2021\n@acme/web 60.50 GBP\n  acme/web,9:00 -10:00\n  acme/app,10:00 -11:00\n",
+                "test",
+            )
+            .unwrap();
+        let e = earnings(&store.clocks, &store.groups, &store.rates).unwrap();
+        let gbp = Some("GBP".to_string());
+        assert_eq!(
+            e[&("acme/web".to_string(), gbp.clone())].amount,
+            Money(16050)
+        );
+        assert_eq!(e[&("acme/app".to_string(), gbp)].amount, Money(5000));
+        assert_eq!(
+            e[&("play".to_string(), Some(String::new()))].amount,
+            Money(1000)
+        );
+        assert_eq!(e[&("home".to_string(), None)].time, Duration::new(1, 0));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::balance::hours_from_str;
+use crate::err::ClockErr;
+use crate::moment::Duration;
+use crate::reader::{Break, Clock};
+use chrono::naive::NaiveDate;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakRule {
+    pub after: Duration,
+    pub min: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    pub max_day: Option<Duration>,
+    pub min_rest: Option<Duration>,
+    pub breaks: Vec<BreakRule>,
+}
+
+impl Rules {
+    /// The German Arbeitszeitgesetz (ArbZG)
+    pub fn de() -> Self {
+        Rules {
+            max_day: Some(Duration::new(10, 0)),
+            min_rest: Some(Duration::new(11, 0)),
+            breaks: vec![
+                BreakRule {
+                    after: Duration::new(6, 0),
+                    min: Duration::new(0, 30),
+                },
+                BreakRule {
+                    after: Duration::new(9, 0),
+                    min: Duration::new(0, 45),
+                },
+            ],
+        }
+    }
+
+    pub fn break_needed(&self, worked: Duration) -> Option<Duration> {
+        self.breaks
+            .iter()
+            .filter(|b| worked > b.after)
+            .map(|b| b.min)
+            .max()
+    }
+}
+
+pub fn breaks_from_str(s: &str) -> anyhow::Result<Vec<BreakRule>> {
+    s.split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| {
+            let (after, min) = p.split_once('=').ok_or_else(|| {
+                anyhow::anyhow!("Break rule '{}' should be like 6=0:30", p.trim())
+            })?;
+            Ok(BreakRule {
+                after: hours_from_str(after)?,
+                min: hours_from_str(min)?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationType {
+    LongDay {
+        worked: Duration,
+        max: Duration,
+    },
+    ShortRest {
+        rest: Duration,
+        min: Duration,
+    },
+    MissingBreak {
+        worked: Duration,
+        taken: Duration,
+        needed: Duration,
+    },
+}
+
+impl Display for ViolationType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ViolationType::LongDay { worked, max } => {
+                write!(f, "Worked {} in the day, over the {} allowed", worked, max)
+            }
+            ViolationType::ShortRest { rest, min } => {
+                write!(f, "Rested {} between days, under the {} needed", rest, min)
+            }
+            ViolationType::MissingBreak {
+                worked,
+                taken,
+                needed,
+            } => write!(
+                f,
+                "Worked {} with {} of breaks, under the {} needed",
+                worked, taken, needed
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Violation {
+    pub date: NaiveDate,
+    pub vtype: ViolationType,
+    pub clocks: Vec<Clock>,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.date.format("%d/%m/%Y"), self.vtype)?;
+        for c in &self.clocks {
+            write!(f, "\n  {}: {} ({})", c.loc, c.describe(), c.lines())?;
+        }
+        Ok(())
+    }
+}
+
+/// A day's clocks are the ones clocked in on it. On a day with breaks marked '~'
+/// only those count as breaks, otherwise any gap between the clocks does.
+pub fn check(
+    clocks: &[Clock],
+    breaks: &[&Break],
+    rules: &Rules,
+) -> Result<Vec<Violation>, ClockErr> {
+    let mut sorted: Vec<&Clock> = clocks.iter().collect();
+    sorted.sort();
+    let mut days: BTreeMap<NaiveDate, Vec<&Clock>> = BTreeMap::new();
+    for c in sorted {
+        days.entry(c.c_in.d).or_default().push(c);
+    }
+
+    let mut res = Vec::new();
+    let mut prev: Option<&Vec<&Clock>> = None;
+    for (date, cl) in &days {
+        let owned = || cl.iter().map(|c| (*c).clone()).collect::<Vec<_>>();
+        let mut worked = Duration::default();
+        let mut gaps = Duration::default();
+        let mut marked: Option<Duration> = None;
+        let mut end = cl[0].c_in;
+        for c in cl {
+            worked += c.duration()?;
+            if c.c_in > end {
+                gaps += c.c_in.time_since(&end);
+            }
+            end = end.max(c.c_out);
+            for b in breaks.iter().filter(|b| b.start == c.c_out) {
+                *marked.get_or_insert_with(Duration::default) += b.duration();
+            }
+        }
+        let taken = marked.unwrap_or(gaps);
+        if let Some(max) = rules.max_day.filter(|m| worked > *m) {
+            res.push(Violation {
+                date: *date,
+                vtype: ViolationType::LongDay { worked, max },
+                clocks: owned(),
+            });
+        }
+        if let Some(needed) = rules.break_needed(worked).filter(|n| taken < *n) {
+            res.push(Violation {
+                date: *date,
+                vtype: ViolationType::MissingBreak {
+                    worked,
+                    taken,
+                    needed,
+                },
+                clocks: owned(),
+            });
+        }
+        if let (Some(min), Some(p)) = (rules.min_rest, prev) {
+            //The latest clockout, as a night shift may be before the last clockin
+            let last = p
+                .iter()
+                .max_by_key(|c| c.c_out)
+                .expect("days are not empty");
+            let rest = cl[0].c_in.time_since(&last.c_out);
+            if rest < min {
+                res.push(Violation {
+                    date: *date,
+                    vtype: ViolationType::ShortRest { rest, min },
+                    clocks: vec![(*last).clone(), cl[0].clone()],
+                });
+            }
+        }
+        prev = Some(cl);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::ClockStore;
+    #[test]
+    pub fn test_compliance_finds_each_rule() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "1/11/2021\n  work,8:00 -12:00\n  12:30 -19:30\n2/11/2021\n  work,5:00 -11:30\n  11:45 -13:00\n3/11/2021\n  work,9:00 -12:00\n  12:30 -16:00\n",
+                "test",
+            )
+            .unwrap();
+        let v = check(&store.clocks, &[], &Rules::de()).unwrap();
+        assert_eq!(v.len(), 4);
+        //11 hours with a 30 minute break
+        assert_eq!(
+            v[0].vtype,
+            ViolationType::LongDay {
+                worked: Duration::new(11, 0),
+                max: Duration::new(10, 0)
+            }
+        );
+        assert!(matches!(v[1].vtype, ViolationType::MissingBreak { .. }));
+        assert_eq!(v[2].clocks.len(), 2);
+        assert_eq!(
+            v[3].vtype,
+            ViolationType::ShortRest {
+                rest: Duration::new(9, 30),
+                min: Duration::new(11, 0)
+            }
+        );
+        assert_eq!(v[3].date, NaiveDate::from_ymd(2021, 11, 2));
+        assert!(v[3].to_string().starts_with("02/11/2021: Rested 09:30"));
+
+        let rules = Rules {
+            max_day: None,
+            min_rest: None,
+            breaks: breaks_from_str("6=0:15").unwrap(),
+        };
+        assert!(check(&store.clocks, &[], &rules).unwrap().is_empty());
+
+        //Only the marked break counts once there is one
+        store
+            .read(
+                "5/11/2021\n  work,8:00 -10:00 ~\n  10:10 -12:00\n  12:30 -16:00\n",
+                "more",
+            )
+            .unwrap();
+        let v = check(&store.clocks, &store.selected_breaks(), &rules).unwrap();
+        assert_eq!(
+            v[0].vtype,
+            ViolationType::MissingBreak {
+                worked: Duration::new(7, 20),
+                taken: Duration::new(0, 10),
+                needed: Duration::new(0, 15)
+            }
+        );
+    }
+    #[test]
+    pub fn test_break_left_open_overnight() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "1/11/2021\n  work,8:00 -17:00 ~\n2/11/2021\n  9:00 -12:00\n",
+                "test",
+            )
+            .unwrap();
+        assert_eq!(store.breaks[0].end, None);
+        assert_eq!(store.breaks[0].duration(), Duration::default());
+        let v = check(&store.clocks, &store.selected_breaks(), &Rules::de()).unwrap();
+        assert_eq!(v.len(), 1);
+        assert_eq!(
+            v[0].vtype,
+            ViolationType::MissingBreak {
+                worked: Duration::new(9, 0),
+                taken: Duration::default(),
+                needed: Duration::new(0, 30)
+            }
+        );
+    }
+}
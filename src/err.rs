@@ -11,16 +11,60 @@ pub struct ParseErr {
 
 impl fmt::Display for ParseErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Error {:?} at l:{},c:{}",
-            self.etype, self.line, self.col
-        )
+        write!(f, "{} at l:{},c:{}", self.etype, self.line, self.col)
     }
 }
 
 impl std::error::Error for ParseErr {}
 
+impl ParseErr {
+    pub fn write_snippet<W: fmt::Write>(&self, w: &mut W, fname: &str, src: &str) -> fmt::Result {
+        let num = self.line.to_string();
+        let pad = " ".repeat(num.len());
+        writeln!(w, "error: {}", self.etype)?;
+        writeln!(w, "{}--> {}:{}:{}", pad, fname, self.line, self.col)?;
+        if let Some(l) = src.lines().nth(self.line.wrapping_sub(1)) {
+            let l = l.trim_end();
+            //Copy tabs from the line so the caret lines up
+            let under: String = l
+                .chars()
+                .take(self.col.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            writeln!(w, "{} |", pad)?;
+            writeln!(w, "{} | {}", num, l)?;
+            writeln!(w, "{} | {}^", pad, under)?;
+        }
+        if let Some(h) = self.etype.hint() {
+            writeln!(w, "{} = hint: {}", pad, h)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseErrs {
+    pub file: String,
+    pub src: String,
+    pub errs: Vec<ParseErr>,
+}
+
+impl fmt::Display for ParseErrs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.errs.len() {
+            1 => writeln!(f, "1 error reading {}", self.file)?,
+            n => writeln!(f, "{} errors reading {}", n, self.file)?,
+        }
+        for e in &self.errs {
+            writeln!(f)?;
+            e.write_snippet(f, &self.file, &self.src)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseErrs {}
+
 #[derive(Debug)]
 pub enum ErrType {
     NoToken,
@@ -37,19 +81,81 @@ pub enum ErrType {
     ClockinNotSet,
     MissingItem,
     MinutesOver60,
+    QuoteNotClosed,
+    NotAnAmount,
+    NoteWithoutClockin,
+    NotAnAbsence,
+    AbsentOverADay,
+    BreakWithoutClockout,
     Expected(TokenType),
 }
 impl std::error::Error for ErrType {}
 
+impl ErrType {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ErrType::NoToken => Some("quote names that use other characters, as in 'Acme Corp'"),
+            ErrType::NotSlashOrColon => {
+                Some("times are written like 9:30, and dates like 3/11/2021")
+            }
+            ErrType::NotYear => Some("set the year for short dates with 'year=2021'"),
+            ErrType::UnexpectedEOF => Some("is a group missing its closing ']'?"),
+            ErrType::JobNotSet => Some("put the job before the time, as in 'work,9:00'"),
+            ErrType::YearNotSet => {
+                Some("add 'year=2021' above, or give the full date as in 3/11/2021")
+            }
+            ErrType::DateNotSet => Some("add a date line such as 3/11/2021 above"),
+            ErrType::ClockinNotSet => {
+                Some("a clockout '-17:00' must follow a clockin such as '9:00'")
+            }
+            ErrType::QuoteNotClosed => Some("quotes must be closed on the same line"),
+            ErrType::NotAnAmount => Some("rates are written like '@acme 80.50 GBP'"),
+            ErrType::NoteWithoutClockin => {
+                Some("put the note after the clockin, as in '9:00 \"fixed login bug\"'")
+            }
+            ErrType::NotAnAbsence => Some(
+                "absences are written like '!vacation', '!sick half' or '!holiday \"New Year\"'",
+            ),
+            ErrType::AbsentOverADay => Some("use '!vacation half' for half a day"),
+            ErrType::BreakWithoutClockout => {
+                Some("mark the break after the clockout it starts at, as in '-12:00 ~'")
+            }
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for ErrType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error {:?}", self)
+        match self {
+            ErrType::NoToken => write!(f, "Unexpected character"),
+            ErrType::NotANumber => write!(f, "Expected a number"),
+            ErrType::NotAnItem => write!(f, "Expected a date, time, job, tag or group"),
+            ErrType::NotSlashOrColon => write!(f, "Expected ':' for a time or '/' for a date"),
+            ErrType::NotATime => write!(f, "Not a valid time"),
+            ErrType::NotYear => write!(f, "Only the year can be set with '='"),
+            ErrType::UnexpectedEOF => write!(f, "The file ended too soon"),
+            ErrType::JobNotSet => write!(f, "Clocked in before any job was set"),
+            ErrType::YearNotSet => write!(f, "Short date used before the year was set"),
+            ErrType::DateNotSet => write!(f, "Clocked in before any date was set"),
+            ErrType::DateNotValid => write!(f, "That date does not exist"),
+            ErrType::ClockinNotSet => write!(f, "Clocked out without being clocked in"),
+            ErrType::MissingItem => write!(f, "Something is missing"),
+            ErrType::MinutesOver60 => write!(f, "Minutes must be less than 60"),
+            ErrType::QuoteNotClosed => write!(f, "Quote is not closed"),
+            ErrType::NotAnAmount => write!(f, "Not an amount of money"),
+            ErrType::NoteWithoutClockin => write!(f, "Note given while not clocked in"),
+            ErrType::NotAnAbsence => write!(f, "Expected vacation, sick or holiday"),
+            ErrType::AbsentOverADay => write!(f, "Absent for more than a day"),
+            ErrType::BreakWithoutClockout => write!(f, "Break marked without a clockout"),
+            ErrType::Expected(tt) => write!(f, "Expected {}", tt.describe()),
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct ClockErr {
-    pub clock: Clock,
+    pub clock: Box<Clock>,
     pub etype: ClockErrType,
 }
 
@@ -58,9 +164,24 @@ pub enum ClockErrType {
     OutBeforeIn,
 }
 
+impl fmt::Display for ClockErrType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClockErrType::OutBeforeIn => write!(f, "Clocked out before clocking in"),
+        }
+    }
+}
+
 impl fmt::Display for ClockErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error with Clock: {:?}  -- {:?}", self.clock, self.etype,)
+        write!(
+            f,
+            "{}: {} : {} ({})",
+            self.clock.loc,
+            self.etype,
+            self.clock.describe(),
+            self.clock.lines()
+        )
     }
 }
 impl std::error::Error for ClockErr {}
@@ -1,20 +1,66 @@
-use crate::moment::{self, today};
-use crate::reader::{Clock, ClockStore, Group};
+use crate::moment::{self, today, Duration};
+use crate::reader::{Clock, ClockStore, Group, Tag};
 use chrono::{naive::NaiveDate, Datelike, Weekday};
 use clap::ArgMatches;
 
 pub type ClockFilter = Box<dyn Fn(&Clock) -> bool>;
+pub type DateFilter = Box<dyn Fn(NaiveDate) -> bool>;
+
+/// Jobs are paths like "client/project/task".
+/// "client" matches the client and everything under it,
+/// "client/*" only the jobs under it.
+pub fn job_matches(pattern: &str, job: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(parent) => job
+            .strip_prefix(parent)
+            .map(|r| r.starts_with('/'))
+            .unwrap_or(false),
+        None => match job.strip_prefix(pattern) {
+            Some(r) => r.is_empty() || r.starts_with('/'),
+            None => false,
+        },
+    }
+}
 
 pub fn by_job<'a, I: Iterator<Item = &'a str>>(jobs: I) -> ClockFilter {
     let jlist: Vec<String> = jobs.map(str::to_string).collect();
-    Box::new(move |c: &Clock| jlist.contains(&c.job))
+    Box::new(move |c: &Clock| jlist.iter().any(|p| job_matches(p, &c.job)))
+}
+
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == s,
+        Some((head, rest)) => match s.strip_prefix(head) {
+            Some(s) => s
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(Some(s.len()))
+                .any(|i| glob_match(rest, &s[i..])),
+            None => false,
+        },
+    }
+}
+
+/// "key" matches a tag with that key whatever its value,
+/// "key=value" checks the value too. Both may use '*' as in "ticket=ABC-*"
+pub fn tag_matches(pattern: &str, t: &Tag) -> bool {
+    match pattern.split_once('=') {
+        Some((k, v)) => {
+            glob_match(k, &t.key)
+                && t.value
+                    .as_deref()
+                    .map(|tv| glob_match(v, tv))
+                    .unwrap_or(false)
+        }
+        None => glob_match(pattern, &t.key),
+    }
 }
 
 pub fn by_tag<'a, I: Iterator<Item = &'a str>>(tags: I) -> ClockFilter {
     let tlist: Vec<String> = tags.map(str::to_string).collect();
     Box::new(move |c: &Clock| {
         for t in &c.tags {
-            if tlist.contains(t) {
+            if tlist.iter().any(|p| tag_matches(p, t)) {
                 return true;
             }
         }
@@ -28,23 +74,31 @@ pub fn by_group<'a, I: Iterator<Item = &'a str>>(tags: I, grps: &[Group]) -> Clo
     for t in tags {
         for g in grps {
             if g.name == t {
-                v.extend(g.members.iter().map(|s| s.clone()));
+                v.extend(g.members.iter().cloned());
             }
         }
     }
-    Box::new(move |c: &Clock| v.contains(&c.job))
+    Box::new(move |c: &Clock| v.iter().any(|p| job_matches(p, &c.job)))
 }
 
-pub fn before(d: NaiveDate) -> ClockFilter {
-    Box::new(move |c: &Clock| c.c_in.d < d)
+pub fn before(d: NaiveDate) -> DateFilter {
+    Box::new(move |x| x < d)
 }
 
-pub fn since(d: NaiveDate) -> ClockFilter {
-    Box::new(move |c: &Clock| c.c_in.d >= d)
+pub fn since(d: NaiveDate) -> DateFilter {
+    Box::new(move |x| x >= d)
 }
 
-pub fn between(f: NaiveDate, t: NaiveDate) -> ClockFilter {
-    Box::new(move |c: &Clock| c.c_in.d >= f && c.c_in.d < t)
+pub fn between(f: NaiveDate, t: NaiveDate) -> DateFilter {
+    Box::new(move |x| x >= f && x < t)
+}
+
+pub fn longer(d: Duration) -> ClockFilter {
+    Box::new(move |c: &Clock| c.duration().map(|t| t >= d).unwrap_or(false))
+}
+
+pub fn shorter(d: Duration) -> ClockFilter {
+    Box::new(move |c: &Clock| c.duration().map(|t| t < d).unwrap_or(false))
 }
 
 pub fn get_args_filter(
@@ -52,7 +106,7 @@ pub fn get_args_filter(
     clocks: &ClockStore,
 ) -> anyhow::Result<Option<ClockFilter>> {
     //Build multi filter
-    let mut filters: Vec<Box<dyn Fn(&Clock) -> bool>> = Vec::new();
+    let mut filters: Vec<ClockFilter> = Vec::new();
 
     if let Some(jobs) = clap.values_of("job_filter") {
         filters.push(by_job(jobs))
@@ -66,6 +120,35 @@ pub fn get_args_filter(
         filters.push(by_group(grps, &clocks.groups));
     }
 
+    if let Some(df) = get_args_date_filter(clap)? {
+        filters.push(Box::new(move |c: &Clock| df(c.c_in.d)));
+    }
+
+    if let Some(ds) = clap.value_of("longer") {
+        filters.push(longer(ds.parse()?));
+    }
+
+    if let Some(ds) = clap.value_of("shorter") {
+        filters.push(shorter(ds.parse()?));
+    }
+
+    match filters.len() {
+        0 => Ok(None),
+        _ => Ok(Some(Box::new(move |c: &Clock| {
+            for f in &filters {
+                if !f(c) {
+                    return false;
+                }
+            }
+            true
+        }))),
+    }
+}
+
+/// The filters on dates alone, so they can be used on absences as well as clocks
+pub fn get_args_date_filter(clap: &ArgMatches) -> anyhow::Result<Option<DateFilter>> {
+    let mut filters: Vec<DateFilter> = Vec::new();
+
     if let Some(wk) = clap.value_of("week_filter") {
         let start = moment::week_yr_from_str(wk, Some(today().year()))?;
         let end = start + chrono::Duration::days(7);
@@ -103,7 +186,7 @@ pub fn get_args_filter(
     }
 
     if let Some(df) = clap.value_of("day_filter") {
-        let start = moment::date_from_str(df, Some(today().year()))?;
+        let start = moment::date_expr(df, today())?;
         let end = start + chrono::Duration::days(1);
         filters.push(between(start, end));
     }
@@ -116,24 +199,43 @@ pub fn get_args_filter(
     }
 
     if let Some(ds) = clap.value_of("since") {
-        let d = moment::date_from_str(ds, Some(today().year()))?;
+        let d = moment::date_expr(ds, today())?;
         filters.push(since(d));
     }
 
     if let Some(ds) = clap.value_of("before") {
-        let d = moment::date_from_str(ds, Some(today().year()))?;
+        let d = moment::date_expr(ds, today())?;
         filters.push(before(d));
     }
 
     match filters.len() {
         0 => Ok(None),
-        _ => Ok(Some(Box::new(move |c: &Clock| {
-            for f in &filters {
-                if !f(c) {
-                    return false;
-                }
-            }
-            true
-        }))),
+        _ => Ok(Some(Box::new(move |d| filters.iter().all(|f| f(d))))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    pub fn test_job_matches_subtrees() {
+        assert!(job_matches("acme", "acme"));
+        assert!(job_matches("acme", "acme/web/login"));
+        assert!(!job_matches("acme", "acmeco"));
+        assert!(!job_matches("acme/*", "acme"));
+        assert!(job_matches("acme/*", "acme/web"));
+        assert!(job_matches("acme/web", "acme/web/login"));
+        assert!(!job_matches("acme/web", "acme/app"));
+    }
+
+    #[test]
+    pub fn test_tag_matches_keys_and_values() {
+        let t = Tag::new("ticket", Some("ABC-123"));
+        assert!(tag_matches("ticket", &t));
+        assert!(tag_matches("ticket=ABC-123", &t));
+        assert!(tag_matches("ticket=ABC-*", &t));
+        assert!(tag_matches("*=*-123", &t));
+        assert!(!tag_matches("ticket=XYZ-*", &t));
+        assert!(!tag_matches("urgent=*", &Tag::new("urgent", None)));
     }
 }
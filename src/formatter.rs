@@ -0,0 +1,211 @@
+use crate::err::{ErrType, ParseErr};
+use crate::parser::{ActionData, Parser};
+use crate::reader::Tag;
+use crate::writer::{
+    absence_str, clock_lead, group_str, quote, rate_str, tag_changes, Block, Line,
+};
+use chrono::naive::NaiveDate;
+
+/// Rewrites a tock file into the canonical layout.
+/// Comments are kept, and the clocks the file describes are left unchanged.
+pub fn format_tock(s: &str) -> Result<String, ParseErr> {
+    let mut p = Parser::new(s);
+    let mut res = String::new();
+    let mut block = Block::new(None);
+
+    let mut year: Option<i32> = None;
+    let mut job: Option<String> = None;
+    let mut written_job: Option<String> = None;
+    let mut tags: Vec<Tag> = Vec::new();
+    let mut written_tags: Vec<Tag> = Vec::new();
+    //The source line of the last line in the block, for trailing comments
+    let mut last_src: Option<usize> = None;
+    //The last line in the block is a clockin waiting for its clockout
+    let mut open = false;
+
+    loop {
+        let action = p.next_action()?;
+        match action.ad {
+            ActionData::Comment(c) => {
+                let comment = c.trim_end().to_string();
+                match block.lines.last_mut() {
+                    Some(l) if last_src == Some(action.line) => l.comment = Some(comment),
+                    None if last_src == Some(action.line) => block.comment = Some(comment),
+                    _ => {
+                        block.lines.push(Line {
+                            comment: Some(comment),
+                            indent: action.col > 1,
+                            ..Default::default()
+                        });
+                        open = false;
+                    }
+                }
+                last_src = None;
+            }
+            ActionData::Group(name, members) => {
+                block.lines.push(Line {
+                    lead: group_str(&name, &members),
+                    ..Default::default()
+                });
+                last_src = Some(action.line);
+                open = false;
+            }
+            ActionData::Rate(target, amount, currency) => {
+                block.lines.push(Line {
+                    lead: rate_str(&target, amount, currency.as_deref()),
+                    ..Default::default()
+                });
+                last_src = Some(action.line);
+                open = false;
+            }
+            ActionData::Absence(kind, half, note) => {
+                block
+                    .lines
+                    .push(Line::clock(absence_str(kind, half, note.as_deref()), None));
+                last_src = Some(action.line);
+                open = false;
+            }
+            ActionData::SetYear(yr) => {
+                year = Some(yr);
+                block.lines.push(Line {
+                    lead: format!("year={}", yr),
+                    ..Default::default()
+                });
+                last_src = Some(action.line);
+                open = false;
+            }
+            ActionData::ShortDate(dd, mm) => {
+                let yr = year.ok_or_else(|| action.as_err(ErrType::YearNotSet))?;
+                let d = NaiveDate::from_ymd_opt(yr, mm, dd)
+                    .ok_or_else(|| action.as_err(ErrType::DateNotValid))?;
+                last_src = new_day(&mut block, d, &mut res).then_some(action.line);
+                open = false;
+            }
+            ActionData::LongDate(dd, mm, yy) => {
+                let d = NaiveDate::from_ymd_opt(yy, mm, dd)
+                    .ok_or_else(|| action.as_err(ErrType::DateNotValid))?;
+                last_src = new_day(&mut block, d, &mut res).then_some(action.line);
+                open = false;
+            }
+            ActionData::SetJob(j) => job = Some(j.into_owned()),
+            ActionData::ClearTags => tags.clear(),
+            ActionData::ClearTag(k) => tags.retain(|i| i.key != k),
+            ActionData::Tag(k, v) => Tag::new(&k, v.as_deref()).set(&mut tags),
+            ActionData::Clockin(t) => {
+                let new_job = match job == written_job {
+                    true => None,
+                    false => job.clone(),
+                };
+                written_job = job.clone();
+                let lead = clock_lead(
+                    new_job.as_deref(),
+                    tag_changes(&written_tags, &tags),
+                    Some(t),
+                );
+                written_tags = tags.clone();
+                block.lines.push(Line::clock(lead, None));
+                last_src = Some(action.line);
+                open = true;
+            }
+            ActionData::Note(n) => {
+                let note = quote(&n, '"');
+                match block.lines.last_mut() {
+                    Some(l) if open => {
+                        l.lead.push(' ');
+                        l.lead.push_str(&note);
+                    }
+                    _ => block.lines.push(Line::clock(note, None)),
+                }
+                last_src = Some(action.line);
+            }
+            ActionData::Break => {
+                match block.lines.last_mut() {
+                    Some(l) if l.out.is_some() && !open => l.brk = true,
+                    _ => block.lines.push(Line {
+                        brk: true,
+                        indent: true,
+                        ..Default::default()
+                    }),
+                }
+                last_src = Some(action.line);
+            }
+            ActionData::Clockout(t) => {
+                add_out(&mut block, t.to_string(), open);
+                last_src = Some(action.line);
+                open = false;
+            }
+            ActionData::ClockoutOn(dd, mm, yy, t) => {
+                let yr = yy
+                    .or(year)
+                    .ok_or_else(|| action.as_err(ErrType::YearNotSet))?;
+                let d = NaiveDate::from_ymd_opt(yr, mm, dd)
+                    .ok_or_else(|| action.as_err(ErrType::DateNotValid))?;
+                add_out(&mut block, format!("{} {}", d.format("%d/%m/%Y"), t), open);
+                last_src = Some(action.line);
+                open = false;
+            }
+            ActionData::End => {
+                //Job and tags set after the last clockin still matter to the next one
+                let new_job = match job == written_job {
+                    true => None,
+                    false => job,
+                };
+                let lead = clock_lead(new_job.as_deref(), tag_changes(&written_tags, &tags), None);
+                if !lead.is_empty() {
+                    block.lines.push(Line::clock(lead, None));
+                }
+                if !block.is_empty() {
+                    //Writing to a String cannot fail
+                    block.write(&mut res).ok();
+                }
+                return Ok(res);
+            }
+        }
+    }
+}
+
+fn add_out(block: &mut Block, out: String, open: bool) {
+    match block.lines.last_mut() {
+        Some(l) if open => l.out = Some(out),
+        _ => block.lines.push(Line::clock(String::new(), Some(out))),
+    }
+}
+
+/// Returns false if the day is the one already open, so its header is not written again
+fn new_day(block: &mut Block, d: NaiveDate, res: &mut String) -> bool {
+    if block.date == Some(d) {
+        return false;
+    }
+    if !block.is_empty() {
+        block.write(res).ok();
+    }
+    *block = Block::new(Some(d));
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::ClockStore;
+    #[test]
+    pub fn test_format_keeps_clocks_and_comments() {
+        let src = "# hours for acme\nyear=2021\n@work 50 GBP\n1/11 # day\n  work,9:00 # late start\n  -12:00\n  # lunch\n  _urgent 13:00 -17:00 ~\n1/11\n  18:00\n2/11/2021\n  !sick half\n  play,__,10:00 -11:30\n  work\n";
+        let formatted = format_tock(src).unwrap();
+        assert!(formatted.contains("# late start"));
+        assert!(formatted.contains("  # lunch"));
+        assert!(formatted.contains("01/11/2021 # day\n"));
+        assert_eq!(formatted.matches("01/11/2021").count(), 1);
+        assert_eq!(format_tock(&formatted).unwrap(), formatted);
+
+        let (mut a, mut b) = (ClockStore::new(), ClockStore::new());
+        let rs_a = a.read(src, "a").unwrap();
+        let rs_b = b.read(&formatted, "b").unwrap();
+        assert_eq!(a.clocks, b.clocks);
+        assert_eq!(a.rates, b.rates);
+        assert_eq!(a.absences, b.absences);
+        assert_eq!(a.breaks, b.breaks);
+        assert_eq!((a.rates.len(), a.absences.len(), a.breaks.len()), (1, 1, 1));
+        assert!(a == b);
+        assert_eq!(rs_a.job, rs_b.job);
+    }
+}
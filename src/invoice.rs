@@ -0,0 +1,407 @@
+use crate::billing::Money;
+use crate::err::ClockErr;
+use crate::moment::Duration;
+use crate::reader::ClockStore;
+use crate::rounding::{self, RoundRule};
+use chrono::naive::NaiveDate;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::{Read, Seek};
+use std::str::FromStr;
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub name: String,
+    pub address: String,
+    pub rate: Money,
+    pub currency: Option<String>,
+    /// In hundredths of a percent, so 17.5% is 1750
+    pub tax: i64,
+    pub terms: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemsBy {
+    Day,
+    Job,
+}
+
+impl FromStr for ItemsBy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "day" => Ok(ItemsBy::Day),
+            "job" => Ok(ItemsBy::Job),
+            _ => Err(anyhow::anyhow!(
+                "Cannot itemize by '{}', expected day or job",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvoiceFormat {
+    Markdown,
+    Html,
+    Text,
+}
+
+impl FromStr for InvoiceFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "md" | "markdown" => Ok(InvoiceFormat::Markdown),
+            "html" => Ok(InvoiceFormat::Html),
+            "text" => Ok(InvoiceFormat::Text),
+            _ => Err(anyhow::anyhow!(
+                "Unknown invoice format '{}', expected one of md,html,text",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Item {
+    pub desc: String,
+    pub time: Duration,
+    pub amount: Money,
+}
+
+#[derive(Debug)]
+pub struct Invoice {
+    pub number: u64,
+    pub date: NaiveDate,
+    pub client: Client,
+    pub items: Vec<Item>,
+    pub subtotal: Money,
+    pub tax: Money,
+    pub total: Money,
+}
+
+pub fn build(
+    store: &ClockStore,
+    client: &Client,
+    by: ItemsBy,
+    rules: &[RoundRule],
+    number: u64,
+    date: NaiveDate,
+) -> Result<Invoice, ClockErr> {
+    let mut times: BTreeMap<String, Duration> = BTreeMap::new();
+    for p in rounding::parts(&store.clocks, &store.groups, rules)? {
+        let k = match by {
+            ItemsBy::Day => p.date.format("%Y-%m-%d").to_string(),
+            ItemsBy::Job => store.clocks[p.clock].job.clone(),
+        };
+        *times.entry(k).or_default() += p.t.rounded;
+    }
+    let items: Vec<Item> = times
+        .into_iter()
+        .map(|(desc, time)| Item {
+            desc,
+            time,
+            amount: client.rate.for_time(time),
+        })
+        .collect();
+    let subtotal: Money = items.iter().map(|i| i.amount).sum();
+    let tax = subtotal.percent(client.tax);
+    Ok(Invoice {
+        number,
+        date,
+        client: client.clone(),
+        items,
+        subtotal,
+        tax,
+        total: subtotal + tax,
+    })
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn md_escape(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+fn hours(d: Duration) -> String {
+    format!("{:.2}", d.mins() as f64 / 60.)
+}
+
+fn tax_str(tax: i64) -> String {
+    match tax % 100 {
+        0 => format!("{}%", tax / 100),
+        _ => format!("{}.{:02}%", tax / 100, tax % 100),
+    }
+}
+
+impl Invoice {
+    fn money(&self, m: Money) -> String {
+        match &self.client.currency {
+            Some(c) => format!("{} {}", m, c),
+            None => m.to_string(),
+        }
+    }
+
+    pub fn render(&self, f: InvoiceFormat) -> String {
+        match f {
+            InvoiceFormat::Markdown => self.markdown(),
+            InvoiceFormat::Html => self.html(),
+            InvoiceFormat::Text => self.text(),
+        }
+    }
+
+    fn totals(&self) -> [(String, String); 3] {
+        [
+            ("Subtotal".to_string(), self.money(self.subtotal)),
+            (
+                format!("Tax ({})", tax_str(self.client.tax)),
+                self.money(self.tax),
+            ),
+            ("Total".to_string(), self.money(self.total)),
+        ]
+    }
+
+    fn markdown(&self) -> String {
+        let mut res = String::new();
+        writeln!(res, "# Invoice {}\n", self.number).ok();
+        writeln!(res, "Date: {}\n", self.date.format("%d/%m/%Y")).ok();
+        writeln!(res, "**{}**  ", self.client.name).ok();
+        for l in self.client.address.lines() {
+            writeln!(res, "{}  ", l).ok();
+        }
+        writeln!(res).ok();
+        writeln!(res, "| Item | Hours | Rate | Amount |").ok();
+        writeln!(res, "|---|---:|---:|---:|").ok();
+        for i in &self.items {
+            writeln!(
+                res,
+                "| {} | {} | {} | {} |",
+                md_escape(&i.desc),
+                hours(i.time),
+                self.client.rate,
+                self.money(i.amount)
+            )
+            .ok();
+        }
+        for (k, v) in self.totals() {
+            writeln!(res, "| **{}** | | | **{}** |", k, v).ok();
+        }
+        if let Some(t) = &self.client.terms {
+            writeln!(res, "\nPayment terms: {}", t).ok();
+        }
+        res
+    }
+
+    fn html(&self) -> String {
+        let mut res = String::new();
+        writeln!(res, "<html><body>").ok();
+        writeln!(res, "<h1>Invoice {}</h1>", self.number).ok();
+        writeln!(res, "<p>Date: {}</p>", self.date.format("%d/%m/%Y")).ok();
+        let addr: Vec<String> = self.client.address.lines().map(html_escape).collect();
+        writeln!(
+            res,
+            "<p><strong>{}</strong><br>{}</p>",
+            html_escape(&self.client.name),
+            addr.join("<br>")
+        )
+        .ok();
+        writeln!(res, "<table>").ok();
+        writeln!(
+            res,
+            "<tr><th>Item</th><th>Hours</th><th>Rate</th><th>Amount</th></tr>"
+        )
+        .ok();
+        for i in &self.items {
+            writeln!(
+                res,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape(&i.desc),
+                hours(i.time),
+                self.client.rate,
+                html_escape(&self.money(i.amount))
+            )
+            .ok();
+        }
+        for (k, v) in self.totals() {
+            writeln!(
+                res,
+                "<tr><th colspan=\"3\">{}</th><td>{}</td></tr>",
+                k,
+                html_escape(&v)
+            )
+            .ok();
+        }
+        writeln!(res, "</table>").ok();
+        if let Some(t) = &self.client.terms {
+            writeln!(res, "<p>Payment terms: {}</p>", html_escape(t)).ok();
+        }
+        writeln!(res, "</body></html>").ok();
+        res
+    }
+
+    fn text(&self) -> String {
+        let mut res = String::new();
+        writeln!(res, "INVOICE {}", self.number).ok();
+        writeln!(res, "Date: {}\n", self.date.format("%d/%m/%Y")).ok();
+        writeln!(res, "{}", self.client.name).ok();
+        for l in self.client.address.lines() {
+            writeln!(res, "{}", l).ok();
+        }
+        writeln!(res).ok();
+        let width = self
+            .items
+            .iter()
+            .map(|i| i.desc.chars().count())
+            .chain(Some(12))
+            .max()
+            .unwrap_or(12);
+        writeln!(
+            res,
+            "{:<width$}  {:>7}  {:>10}  {:>14}",
+            "Item",
+            "Hours",
+            "Rate",
+            "Amount",
+            width = width
+        )
+        .ok();
+        for i in &self.items {
+            writeln!(
+                res,
+                "{:<width$}  {:>7}  {:>10}  {:>14}",
+                i.desc,
+                hours(i.time),
+                self.client.rate,
+                self.money(i.amount),
+                width = width
+            )
+            .ok();
+        }
+        writeln!(res, "{}", "-".repeat(width + 39)).ok();
+        for (k, v) in self.totals() {
+            writeln!(res, "{:<w$}  {:>14}", k, v, w = width + 21).ok();
+        }
+        if let Some(t) = &self.client.terms {
+            writeln!(res, "\nPayment terms: {}", t).ok();
+        }
+        res
+    }
+}
+
+/// If take is set and `use_number` succeeds, the new number is saved.
+/// The state file stays locked until then, so no one else can take the same number.
+pub fn next_number<F: FnOnce(u64) -> anyhow::Result<()>>(
+    path: &str,
+    take: bool,
+    use_number: F,
+) -> anyhow::Result<()> {
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut f = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    f.lock()?;
+    let mut s = String::new();
+    f.read_to_string(&mut s)?;
+    let last: u64 = match s.trim() {
+        "" => 0,
+        n => n
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invoice state file {} is not a number", path))?,
+    };
+    let next = last + 1;
+    use_number(next)?;
+    if take {
+        f.set_len(0)?;
+        f.rewind()?;
+        std::io::Write::write_all(&mut f, format!("{}\n", next).as_bytes())?;
+    }
+    f.unlock()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::ClockStore;
+    #[test]
+    pub fn test_invoice_totals() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "1/11/2021\n  web,9:00 -10:08\n  app,10:08 -11:00\n2/11/2021\n  web,9:00 -9:20\n",
+                "test",
+            )
+            .unwrap();
+        let client = Client {
+            name: "Acme".to_string(),
+            address: "1 Road\nTown".to_string(),
+            rate: "60".parse().unwrap(),
+            currency: Some("GBP".to_string()),
+            tax: 2000,
+            terms: None,
+        };
+        let rules = vec![RoundRule {
+            target: None,
+            rounding: "15".parse().unwrap(),
+        }];
+        let inv = build(
+            &store,
+            &client,
+            ItemsBy::Job,
+            &rules,
+            7,
+            NaiveDate::from_ymd(2021, 11, 30),
+        )
+        .unwrap();
+        //web 1:08 -> 1:15 and 0:20 -> 0:15, app 0:52 -> 0:45
+        assert_eq!(inv.items[1].time, Duration::new(1, 30));
+        assert_eq!(inv.subtotal.to_string(), "135.00");
+        assert_eq!(inv.tax.to_string(), "27.00");
+        assert_eq!(inv.total.to_string(), "162.00");
+        let md = inv.render(InvoiceFormat::Markdown);
+        assert!(md.contains("| web | 1.50 | 60.00 | 90.00 GBP |"));
+        assert!(inv
+            .render(InvoiceFormat::Html)
+            .contains("<td>162.00 GBP</td>"));
+
+        let by_day = build(&store, &client, ItemsBy::Day, &[], 7, inv.date).unwrap();
+        assert_eq!(by_day.items[0].desc, "2021-11-01");
+        assert_eq!(by_day.items[0].time, Duration::new(2, 0));
+
+        let mut piped = ClockStore::new();
+        piped
+            .read("1/11/2021\n  'web|app',9:00 -10:00\n", "test")
+            .unwrap();
+        let inv = build(&piped, &client, ItemsBy::Job, &[], 7, inv.date).unwrap();
+        assert!(inv
+            .render(InvoiceFormat::Markdown)
+            .contains("| web\\|app | 1.00 |"));
+    }
+
+    #[test]
+    pub fn test_number_kept_when_unused() {
+        let path = std::env::temp_dir().join(format!("wt_invoice_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "4\n").unwrap();
+        assert!(next_number(path, true, |_| Err(anyhow::anyhow!("disk full"))).is_err());
+        let mut got = 0;
+        next_number(path, true, |n| {
+            got = n;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(got, 5);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "5\n");
+        std::fs::remove_file(path).ok();
+    }
+}
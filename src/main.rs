@@ -1,15 +1,24 @@
 use clap::{clap_app, crate_version};
 
+pub mod absence;
+pub mod balance;
+pub mod billing;
+pub mod compliance;
 pub mod err;
 pub mod filter;
+pub mod formatter;
+pub mod invoice;
 pub mod moment;
 pub mod parser;
 pub mod reader;
+pub mod report;
+pub mod rounding;
 pub mod tokenize;
+pub mod writer;
 use chrono::Datelike;
 use clap_conf::*;
 use err_tools::*;
-use moment::{Moment, STime};
+use moment::{Duration, Moment, TimeExpr};
 use reader::*;
 use std::fmt::Write;
 use std::io::Read;
@@ -31,67 +40,138 @@ fn main() -> anyhow::Result<()> {
         (@subcommand in =>
             (about:"Clock into a job (automatically clocks out of the current job)")
             (@arg job: -j --job +takes_value "The job to clockin to")
-            (@arg at: -a --at +takes_value "Time to clockin")
-            (@arg date : -d --date +takes_value "Date to clockin")
+            (@arg at: -a --at +takes_value +allow_hyphen_values "Time to clockin, as in 9:30, 9am, 14.30 or -15m")
+            (@arg date : -d --date +takes_value +allow_hyphen_values "Date to clockin, as in 17/10, 2026-10-17, yesterday, mon or -2d")
+            (@arg note : -n --note +takes_value "A note on what is being done")
+            (@arg tag: -t --tag +takes_value +multiple number_of_values(1) "Add a tag, as key or key=value")
+            (@arg untag: -u --untag +takes_value +multiple number_of_values(1) "Remove the tag with this key")
+            (@arg clear_tags: -c --clear_tags "Remove all tags before adding any")
+        )
+        (@subcommand switch =>
+            (about:"Clock in again now, changing only the job or only the tags")
+            (@arg job: -j --job +takes_value "The job to switch to")
+            (@arg at: -a --at +takes_value +allow_hyphen_values "Time to switch")
+            (@arg note : -n --note +takes_value "A note on what is being done")
+            (@arg tag: -t --tag +takes_value +multiple number_of_values(1) "Add a tag, as key or key=value")
+            (@arg untag: -u --untag +takes_value +multiple number_of_values(1) "Remove the tag with this key")
+            (@arg clear_tags: -c --clear_tags "Remove all tags before adding any")
         )
         (@subcommand out =>
             (about:"Clock out of the current job")
-            (@arg long_day:-l --long_day "Allow Days times greater than 24 hours")
+            (@arg long_day:-l --long_day "Allow clocks longer than 24 hours")
             (@arg same_day:-s --same_day "Clock out on same day as last clockin")
-            (@arg date : -d --date "Set the date of the clock out")
-            (@arg at:-a --at +takes_value "The time to clockout at")
+            (@arg date : -d --date +takes_value +allow_hyphen_values "Set the date of the clock out")
+            (@arg at:-a --at +takes_value +allow_hyphen_values "The time to clockout at, as in 17:30, 5.30pm or -10m")
+        )
+        (@subcommand pause =>
+            (about:"Clock out for a break, to resume the same job and tags after")
+            (@arg long_day:-l --long_day "Allow clocks longer than 24 hours")
+            (@arg same_day:-s --same_day "Pause on same day as last clockin")
+            (@arg date : -d --date +takes_value +allow_hyphen_values "Set the date of the pause")
+            (@arg at:-a --at +takes_value +allow_hyphen_values "The time to pause at")
+        )
+        (@subcommand resume =>
+            (about:"Clock back in after a break, to the job and tags from before it")
+            (@arg at: -a --at +takes_value +allow_hyphen_values "Time to resume")
+            (@arg date : -d --date +takes_value +allow_hyphen_values "Date to resume")
+            (@arg note : -n --note +takes_value "A note on what is being done")
         )
         (@subcommand last =>
             (about:"Clock in a duration ago and out again")
-            (@arg duration:+required "The duration")
+            (@arg duration:+required "The duration, as in 1h30m, 90m, 1.5h, 1:30, hour or half")
+            (@arg until:-u --until +takes_value +allow_hyphen_values "The time to clock out at, [default] now")
+            (@arg date : -d --date +takes_value +allow_hyphen_values "The date to clock out on, needs --until")
             (@arg job:-j --job +takes_value "The job to clock in and out of")
+            (@arg note : -n --note +takes_value "A note on what was done")
         )
         (@subcommand write =>
+            (about:"Write the selected clocks out in another format")
             (@arg format:--format +takes_value "Output format yaml,json,[default] tock")
             (@arg write_file:-f +takes_value "Write output to a file (instead of stdout)")
         )
+        (@subcommand earnings =>
+            (about:"Show what the selected clocks earn, at the rate for each clock's date")
+        )
+        (@subcommand invoice =>
+            (about:"Write an invoice for the selected clocks, for a client set in the config")
+            (@arg client:+required "The client's key under [clients] in the config")
+            (@arg format:--format +takes_value "Output format md,html,[default] text")
+            (@arg per:--per +takes_value "One line item per day or [default] job")
+            (@arg round:--round +takes_value "Round every clock as in 15, 15:up or 6:nearest:day, in place of the config rules")
+            (@arg preview:--preview "Do not use up an invoice number")
+            (@arg write_file:-f +takes_value "Write output to a file (instead of stdout)")
+        )
+        (@subcommand balance =>
+            (about:"Show the flexitime balance against the daily targets in the config")
+            (@arg per:--per +takes_value "Show the balance per day, [default] week or month")
+        )
+        (@subcommand leave =>
+            (about:"Show the leave allowed, taken and booked for each type, from the config [leave]")
+            (@arg year:--year +takes_value "The year to show, [default] this year")
+        )
+        (@subcommand holidays =>
+            (about:"Add the public holidays from a list file to the main file")
+            (@arg list:+required "One holiday a line, as in '25/12/2026 Christmas Day' or '2026-12-25,Christmas Day'")
+        )
+        (@subcommand compliance =>
+            (about:"Check the selected clocks against working time rules, by default the German ones")
+            (@arg rules:--rules +takes_value "A rule set under [compliance.<name>] in the config, or de for the German rules")
+        )
+        (@subcommand check =>
+            (about:"Report clocks that overlap or repeat, across the main and history files")
+        )
+        (@subcommand fmt =>
+            (about:"Rewrite the main file in the standard layout, keeping comments")
+            (@arg in_place:-i --in_place "Overwrite the file instead of printing the result")
+        )
         (@arg job_filter: -j --job +takes_value #{1,20}"filter by job")
         (@arg group_filter:-g --group +takes_value #{1,20} "filter by group")
-        (@arg tag_filter:--tag +takes_value #{1,20} "filter by tag")
+        (@arg tag_filter:--tag +takes_value #{1,20} "filter by tag, as key, key=value or key=pattern*")
 
         (@arg last: -l "filter by the last day")
         (@arg week_filter: --week +takes_value "filter by week (1-53)")
         (@arg this_week: -w --this_week "filter by this week")
         (@arg month_filter : --month +takes_value "filter by month")
         (@arg this_month:-m --this_month "Filter by this month")
-        (@arg day_filter:--dat +takes_value "filter by day")
+        (@arg day_filter:--dat +takes_value +allow_hyphen_values "filter by day")
         (@arg today:-t --today "filter by today")
 
-        (@arg since:--since +takes_value "filter after including date")
-        (@arg before:--before +takes_value "filter before not including date")
+        (@arg since:--since +takes_value +allow_hyphen_values "filter after including date, as in 1/10, 2026-10-01, mon or -7d")
+        (@arg before:--before +takes_value +allow_hyphen_values "filter before not including date")
+        (@arg longer:--longer +takes_value "filter clocks of at least a duration, as in 2h or 1h30m")
+        (@arg shorter:--shorter +takes_value "filter clocks of less than a duration, as in 15m")
+        (@arg budget:--budget +takes_value "Show the time left of a budget for the selected clocks, as in 20h")
 
         (@arg file:-f --file +takes_value "The main file")
         (@arg history:-h --history +takes_value #{0,30} "Other files to process")
         (@arg stdin:--stdin "read stdin instead of any files")
         (@arg print:-p --print "print all selected jobs")
+        (@arg by:--by +takes_value "Group totals by job,tag,tag:<key>,group,day,week,month or year, comma separated to nest them")
     )
     .get_matches();
 
-    let cfg = clap_conf::with_toml_env(&clap, &["{HOME}/.config/work_tock/init.toml"]);
+    let cfg = clap_conf::with_toml_env(&clap, ["{HOME}/.config/work_tock/init.toml"]);
 
-    if let Some(_) = clap.subcommand_matches("complete") {
+    if clap.subcommand_matches("complete").is_some() {
         return complete(&cfg);
     }
 
+    if let Some(fsub) = clap.subcommand_matches("fmt") {
+        return format_file(fsub, &clap, &cfg);
+    }
+
     let mut clocks = ClockStore::new();
 
     let (fname, read_state) = if clap.is_present("stdin") {
         let mut input = std::io::stdin();
         let mut s = String::new();
         input.read_to_string(&mut s).e_str("could not read stdin")?;
-        (None, clocks.read(&s)?)
+        (None, clocks.read(&s, "<stdin>")?)
     } else {
         if let Some(v) = cfg.grab_multi().arg("history").conf("history").done() {
             for f in v {
                 let s = load_file(&f)?;
-                let rs = clocks
-                    .read(&s)
-                    .e_string(format!("Error in history file : {}", f))?;
+                let rs = clocks.read(&s, &f)?;
                 if let Some(_in) = rs.curr_in {
                     return e_string(format!("History file clocked in at end : {}", f));
                 }
@@ -104,40 +184,218 @@ fn main() -> anyhow::Result<()> {
             .rep_env()
             .e_str("could not get filename")?;
         let s = load_file(&fname)?;
-        (Some(fname), clocks.read(&s)?)
+        let rs = clocks.read(&s, &fname)?;
+        (Some(fname), rs)
     };
 
     //let today = s_time::today();
     if let Some(ci) = &read_state.curr_in {
-        if STime::now() < ci.c_in.t {
+        if Moment::now() < ci.c_in {
             return e_str("You are clocked in, in the future");
         }
         ci.print();
-        clocks.clocks.push(ci.clone().as_clock(STime::now()));
     }
 
     if let Some(isub) = clap.subcommand_matches("in") {
-        clock_in(isub, read_state, &fname)?;
-        return Ok(());
+        return append_locked(&fname, &read_state, |rs| clock_in(isub, rs));
+    }
+
+    if let Some(ssub) = clap.subcommand_matches("switch") {
+        return append_locked(&fname, &read_state, |rs| {
+            if rs.curr_in.is_none() {
+                return e_str("Not clocked in, so there is nothing to switch, use 'in'");
+            }
+            if !["job", "tag", "untag", "clear_tags"]
+                .iter()
+                .any(|a| ssub.is_present(a))
+            {
+                return e_str("Give a job or tags to switch to");
+            }
+            clock_in(ssub, rs)
+        });
     }
 
     if let Some(osub) = clap.subcommand_matches("out") {
-        clock_out(osub, &read_state, &fname)?;
-        return Ok(());
+        return append_locked(&fname, &read_state, |rs| clock_out(osub, rs));
+    }
+
+    if let Some(psub) = clap.subcommand_matches("pause") {
+        return append_locked(&fname, &read_state, |rs| Ok(clock_out(psub, rs)? + " ~"));
+    }
+
+    if let Some(rsub) = clap.subcommand_matches("resume") {
+        return append_locked(&fname, &read_state, |rs| match rs.paused {
+            Some(p) if p.d != in_moment(rsub)?.d => {
+                e_str("The break started on another day, so use 'in' to start the day")
+            }
+            Some(_) => clock_in(rsub, rs),
+            None => e_str("Not on a break, so there is nothing to resume"),
+        });
     }
 
     if let Some(lsub) = clap.subcommand_matches("last") {
-        clock_last(lsub, &read_state, &fname)?;
+        return append_locked(&fname, &read_state, |rs| clock_last(lsub, rs));
+    }
+
+    if let Some(wsub) = clap.subcommand_matches("write") {
+        let mut open = read_state.curr_in.as_ref();
+        if let Some(f) = filter::get_args_filter(&clap, &clocks)? {
+            //The open clock is kept if it would be as a clock up to now
+            open = open.filter(|ci| f(&(*ci).clone().as_clock(Moment::now(), ci.loc.clone())));
+            clocks.clocks.retain(f);
+        }
+        if let Some(f) = filter::get_args_date_filter(&clap)? {
+            clocks.absences.retain(|a| f(a.date));
+        }
+        return write_clocks(wsub, &clocks, open);
+    }
+
+    if clap.subcommand_matches("earnings").is_some() {
+        if let Some(f) = filter::get_args_filter(&clap, &clocks)? {
+            clocks.clocks.retain(f);
+        }
+        //Rates in the tock files are listed last so they win over the config
+        let mut rates = config_rates(&cfg)?;
+        rates.extend(clocks.rates.iter().cloned());
+        let e = billing::earnings(&clocks.clocks, &clocks.groups, &rates)?;
+        print!("{}", billing::render_earnings(&e));
         return Ok(());
     }
 
-    if let Some(f) = filter::get_args_filter(&clap, &clocks)? {
-        clocks.clocks.retain(f);
+    if let Some(isub) = clap.subcommand_matches("invoice") {
+        if let Some(f) = filter::get_args_filter(&clap, &clocks)? {
+            clocks.clocks.retain(f);
+        }
+        return write_invoice(isub, &cfg, &clocks);
     }
 
-    let time_map = clocks.as_time_map(clap.is_present("print"));
+    if let Some(lsub) = clap.subcommand_matches("leave") {
+        let today = moment::today();
+        let year = match lsub.value_of("year") {
+            Some(y) => y.parse()?,
+            None => today.year(),
+        };
+        let allowances = config_allowances(&cfg)?;
+        let rows = absence::leave(&clocks.absences, &allowances, year, today);
+        print!("{}", absence::render_leave(&rows, year));
+        return Ok(());
+    }
 
-    println!("{:?}", time_map);
+    if let Some(hsub) = clap.subcommand_matches("holidays") {
+        let list = load_file(hsub.value_of("list").e_str("No holiday list given")?)?;
+        let mut ws = String::new();
+        for (d, name) in absence::read_holiday_list(&list)? {
+            //Days already marked off are left alone, so a list can be imported twice
+            if clocks.absences.iter().any(|a| a.date == d) {
+                continue;
+            }
+            let note = Some(name.as_str()).filter(|n| !n.is_empty());
+            writeln!(ws, "{}", d.format("%d/%m/%Y"))?;
+            writeln!(
+                ws,
+                "  {}",
+                writer::absence_str(absence::AbsenceKind::Holiday, false, note)
+            )?;
+        }
+        if ws.is_empty() {
+            println!("No new holidays to add");
+            return Ok(());
+        }
+        ws.pop();
+        return append_locked(&fname, &read_state, |_| Ok(ws));
+    }
+
+    if let Some(csub) = clap.subcommand_matches("compliance") {
+        if let Some(f) = filter::get_args_filter(&clap, &clocks)? {
+            clocks.clocks.retain(f);
+        }
+        let rules = config_compliance(csub, &cfg)?;
+        let violations = compliance::check(&clocks.clocks, &clocks.selected_breaks(), &rules)?;
+        for v in &violations {
+            println!("{}", v);
+        }
+        return match violations.len() {
+            0 => Ok(()),
+            n => e_string(format!("{} violations found", n)),
+        };
+    }
+
+    if clap.subcommand_matches("check").is_some() {
+        let collisions = clocks.check_collisions();
+        for c in &collisions {
+            println!("{}", c);
+        }
+        return match collisions.len() {
+            0 => Ok(()),
+            n => e_string(format!("{} collisions found", n)),
+        };
+    }
+
+    if let Some(ci) = &read_state.curr_in {
+        clocks
+            .clocks
+            .push(ci.clone().as_clock(Moment::now(), ci.loc.clone()));
+    }
+
+    let targets = config_targets(&cfg)?;
+
+    if let Some(bsub) = clap.subcommand_matches("balance") {
+        if let Some(f) = filter::get_args_filter(&clap, &clocks)? {
+            clocks.clocks.retain(f);
+        }
+        //The absences too, or the balance would start from the first one
+        if let Some(f) = filter::get_args_date_filter(&clap)? {
+            clocks.absences.retain(|a| f(a.date));
+        }
+        let per = match bsub.value_of("per") {
+            Some(p) => p.parse()?,
+            None => balance::Period::Week,
+        };
+        let rows = balance::balance(
+            &clocks.clocks,
+            &clocks.absences,
+            &clocks.selected_breaks(),
+            &targets,
+            per,
+            moment::today(),
+        )?;
+        print!("{}", balance::render_balance(&rows));
+        return Ok(());
+    }
+
+    let filter = filter::get_args_filter(&clap, &clocks)?;
+    if let Some(f) = &filter {
+        clocks.clocks.retain(|c| f(c));
+    }
+
+    let rules = config_rounding(&cfg)?;
+    //Worked out before the absences are credited, which do not use up the budget
+    let used = match clap.is_present("budget") {
+        true => Some(report::budget_used(&clocks.clocks, &clocks.groups, &rules)?),
+        false => None,
+    };
+
+    //Absences count in the reports as their credited time, under "(vacation)" and the like
+    let mut credited = absence::credited_clocks(&clocks.absences, &targets);
+    if let Some(f) = &filter {
+        credited.retain(|c| f(c));
+    }
+    clocks.clocks.extend(credited);
+
+    let time_map = clocks.as_time_map(clap.is_present("print"), &rules)?;
+
+    match clap.value_of("by") {
+        Some(by) => {
+            let dims = report::dims_from_str(by)?;
+            let root = report::group_by(&clocks.clocks, &clocks.groups, &dims, &rules)?;
+            print!("{}", report::render_tree(&root, !rules.is_empty()));
+        }
+        None => print!("{}", report::render_summary(&time_map, !rules.is_empty())),
+    }
+    print!("{}", report::render_breaks(&clocks.selected_breaks()));
+    if let (Some(b), Some(used)) = (clap.value_of("budget"), used) {
+        print!("{}", report::render_budget(b.parse()?, used));
+    }
 
     Ok(())
 }
@@ -152,35 +410,73 @@ pub fn complete<'a, H: clap_conf::Getter<'a, String>>(cfg: &'a H) -> anyhow::Res
         let s = load_file(&f)?;
         let mut p = parser::Parser::new(&s);
         while let Ok(Some(s)) = p.next_ident() {
-            match mp.get(s) {
-                Some(()) => {}
-                None => {
-                    mp.insert(s.to_string(), ());
-                }
-            }
+            mp.insert(s.into_owned(), ());
         }
     }
 
     for k in mp.keys() {
-        print!("{} ", k);
+        print!("{} ", writer::quote_job(k));
     }
-    print!("\n");
+    println!();
     Ok(())
 }
 
-pub fn clock_in(
-    isub: &clap::ArgMatches,
-    read_state: reader::ReadState,
-    fname: &Option<String>,
+pub fn format_file<'a, H: clap_conf::Getter<'a, String>>(
+    fsub: &clap::ArgMatches,
+    clap: &clap::ArgMatches,
+    cfg: &'a H,
 ) -> anyhow::Result<()> {
-    let today = moment::today();
-    let mut ws = "".to_string();
+    let (fname, s) = if clap.is_present("stdin") {
+        let mut s = String::new();
+        std::io::stdin()
+            .read_to_string(&mut s)
+            .e_str("could not read stdin")?;
+        (None, s)
+    } else {
+        let fname = cfg
+            .grab()
+            .arg("file")
+            .conf("config.file")
+            .rep_env()
+            .e_str("could not get filename")?;
+        let s = load_file(&fname)?;
+        (Some(fname), s)
+    };
+    let (mut before, mut after) = (ClockStore::new(), ClockStore::new());
+    before.read(&s, fname.as_deref().unwrap_or("<stdin>"))?;
+    let res = formatter::format_tock(&s)?;
+    after.read(&res, "formatted output")?;
+    if before != after {
+        return e_str("Formatting would change the clocks, file left unchanged");
+    }
+
+    match (fname, fsub.is_present("in_place")) {
+        (Some(nm), true) => std::fs::write(nm, res)?,
+        _ => print!("{}", res),
+    }
+    Ok(())
+}
+
+fn in_moment(isub: &clap::ArgMatches) -> anyhow::Result<Moment> {
+    let now = Moment::now();
     let indate = match isub.value_of("date") {
-        Some(d) => moment::date_from_str(d, Some(today.year()))?,
-        None => today,
+        Some(d) => moment::date_expr(d, now.d)?,
+        None => now.d,
     };
-    if Some(indate) != read_state.date {
-        write!(ws, "{}\n", indate.format("%d/%m/%Y"))?;
+    match isub.value_of("at") {
+        Some(t) => TimeExpr::from_str(t)?.on(indate, now),
+        None => match isub.value_of("date") {
+            Some(_) => e_str("Date Time required when date given"),
+            None => Ok(now),
+        },
+    }
+}
+
+pub fn clock_in(isub: &clap::ArgMatches, read_state: &ReadState) -> anyhow::Result<String> {
+    let c_in = in_moment(isub)?;
+    let mut ws = "".to_string();
+    if Some(c_in.d) != read_state.date {
+        writeln!(ws, "{}", c_in.d.format("%d/%m/%Y"))?;
     }
     ws.push('\t');
     let job = isub
@@ -188,25 +484,44 @@ pub fn clock_in(
         .map(String::from)
         .or(read_state.job.clone())
         .e_str("No Job provided for clock in")?;
-    if Some(&job) != read_state.job.as_ref() {
-        write!(ws, "{},", job)?;
+    let new_job = match Some(&job) != read_state.job.as_ref() {
+        true => Some(job.as_str()),
+        false => None,
+    };
+    let tags = tag_args(isub, &read_state.tags)?;
+    ws.push_str(&writer::clock_lead(
+        new_job,
+        writer::tag_changes(&read_state.tags, &tags),
+        Some(c_in.t),
+    ));
+    if let Some(n) = isub.value_of("note") {
+        write!(ws, " {}", writer::quote(n, '"'))?;
     }
-    let time = match isub.value_of("at") {
-        Some(t) => STime::from_str(t)?,
-        None => match isub.value_of("date") {
-            Some(_) => e_str("Date Time required when date given")?,
-            None => STime::now(),
-        },
+    Ok(ws)
+}
+
+fn tag_args(sub: &clap::ArgMatches, curr: &[Tag]) -> anyhow::Result<Vec<Tag>> {
+    let mut res = match sub.is_present("clear_tags") {
+        true => Vec::new(),
+        false => curr.to_vec(),
     };
-    write!(ws, "{}", time)?;
-    print_result(fname, &ws)
+    for k in sub.values_of("untag").into_iter().flatten() {
+        if !res.iter().any(|t| t.key == k) {
+            return e_string(format!("No tag '{}' to remove", k));
+        }
+        res.retain(|t| t.key != k);
+    }
+    for t in sub.values_of("tag").into_iter().flatten() {
+        let tag = match t.split_once('=') {
+            Some((k, v)) => Tag::new(k, Some(v)),
+            None => Tag::new(t, None),
+        };
+        tag.set(&mut res);
+    }
+    Ok(res)
 }
 
-pub fn clock_out(
-    osub: &clap::ArgMatches,
-    rs: &ReadState,
-    fname: &Option<String>,
-) -> anyhow::Result<()> {
+pub fn clock_out(osub: &clap::ArgMatches, rs: &ReadState) -> anyhow::Result<String> {
     let curr_in = match &rs.curr_in {
         Some(i) => i,
         None => return err_tools::e_str("Cannot Clock out if not clocked in"),
@@ -214,58 +529,65 @@ pub fn clock_out(
 
     let now = Moment::now();
     let dfs = match osub.value_of("date") {
-        Some(d) => Some(moment::date_from_str(d, Some(now.d.year()))?),
+        Some(d) => Some(moment::date_expr(d, now.d)?),
         None => None,
     };
 
     let out = match (dfs, osub.value_of("at"), osub.is_present("same_day")) {
-        (_, Some(ts), true) => Moment::new(curr_in.c_in.d, STime::from_str(ts)?),
-        (Some(ds), Some(ts), false) => Moment::new(ds, STime::from_str(ts)?),
+        (_, Some(ts), true) => TimeExpr::from_str(ts)?.on(curr_in.c_in.d, now)?,
+        (Some(ds), Some(ts), false) => TimeExpr::from_str(ts)?.on(ds, now)?,
         (Some(_), None, _) | (_, None, true) => {
             return e_str("Cannot use current time when applying a previous date")
         }
-        (None, Some(ts), false) => Moment::new(now.d, STime::from_str(ts)?),
+        (None, Some(ts), false) => TimeExpr::from_str(ts)?.on(now.d, now)?,
         (None, None, false) => now,
     };
-    let otime = match (
-        (out.d - curr_in.c_in.d).num_days(),
-        osub.is_present("long_day"),
-    ) {
-        (n, _) if n < 0 => return e_str("Cannot Clock out before clock in"),
-        (0, _) if out.t < curr_in.c_in.t => return e_str("Cannot Clock out before Clock in"),
-        (0, _) => out.t,
-        (n, true) => out.t + STime::new(n as u32 * 24, 0),
-        (_, false) => return e_str("If clocking out the next day, please mark -l for long_day"),
-    };
-
-    match &fname {
-        Some(nm) => {
-            let mut f = std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .append(true)
-                .open(nm)?;
-            std::io::Write::write_fmt(&mut f, format_args!("  -{}\n", otime))?;
-        }
-        None => {
-            println!("  -{}", otime);
-        }
+    if out < curr_in.c_in {
+        return e_str("Cannot Clock out before Clock in");
     }
-    Ok(())
+    if out.time_since(&curr_in.c_in) > Duration::new(24, 0) && !osub.is_present("long_day") {
+        return e_str("Clock would be longer than 24 hours, please mark -l for long_day");
+    }
+
+    let ws = match Some(out.d) == rs.date {
+        true => format!("  -{}", out.t),
+        false => format!("  -{} {}", out.d.format("%d/%m/%Y"), out.t),
+    };
+    Ok(ws)
 }
 
-pub fn clock_last(
-    osub: &clap::ArgMatches,
-    rs: &ReadState,
-    fname: &Option<String>,
-) -> anyhow::Result<()> {
+pub fn clock_last(osub: &clap::ArgMatches, rs: &ReadState) -> anyhow::Result<String> {
     if let Some(i) = &rs.curr_in {
         return e_string(format!("Currently clocked in for {:?}", i));
     }
-    let today = moment::today();
+    let duration = match osub.value_of("duration") {
+        Some("hour") => Duration::new(1, 0),
+        Some("half") => Duration::new(0, 30),
+        Some(s) => Duration::from_str(s)?,
+        None => Duration::new(1, 0),
+    };
+    if duration.is_negative() {
+        return e_str("Cannot clock a negative duration");
+    }
+    let now = Moment::now();
+    let t_out = match (osub.value_of("until"), osub.value_of("date")) {
+        (Some(t), d) => {
+            let d = match d {
+                Some(d) => moment::date_expr(d, now.d)?,
+                None => now.d,
+            };
+            TimeExpr::from_str(t)?.on(d, now)?
+        }
+        (None, Some(_)) => return e_str("Time required with --until when date given"),
+        (None, None) => now,
+    };
+    let t_in = t_out
+        .checked_sub(duration)
+        .e_str("Duration is too long to clock")?;
+
     let mut ws = "".to_string();
-    if Some(today) != rs.date {
-        write!(ws, "{}\n", today.format("%d/%m/%Y"))?;
+    if Some(t_in.d) != rs.date {
+        writeln!(ws, "{}", t_in.d.format("%d/%m/%Y"))?;
     }
     write!(ws, "\t")?;
     let job = osub
@@ -274,38 +596,261 @@ pub fn clock_last(
         .or(rs.job.clone())
         .e_str("No Job provided for clock in")?;
     if Some(&job) != rs.job.as_ref() {
-        write!(ws, "{},", job)?;
+        write!(ws, "{},", writer::quote_job(&job))?;
     }
 
-    let duration = match osub.value_of("duration") {
-        Some("hour") => STime::new(1, 0),
-        Some("half") => STime::new(0, 30),
-        Some(s) => STime::from_str(s)?,
-        None => STime::new(1, 0),
+    write!(ws, "{}", t_in.t)?;
+    if let Some(n) = osub.value_of("note") {
+        write!(ws, " {}", writer::quote(n, '"'))?;
+    }
+    match t_out.d == t_in.d {
+        true => write!(ws, "\n  -{}", t_out.t)?,
+        false => write!(ws, "\n  -{} {}", t_out.d.format("%d/%m/%Y"), t_out.t)?,
+    }
+
+    Ok(ws)
+}
+
+pub fn write_clocks(
+    wsub: &clap::ArgMatches,
+    clocks: &ClockStore,
+    open: Option<&Clockin>,
+) -> anyhow::Result<()> {
+    let format = match wsub.value_of("format") {
+        Some(f) => f.parse()?,
+        None => writer::OutFormat::Tock,
+    };
+    let s = writer::write_store(clocks, open, format)?;
+    match wsub.value_of("write_file") {
+        Some(nm) => std::fs::write(nm, s)?,
+        None => print!("{}", s),
+    }
+    Ok(())
+}
+
+/// Appends the entry built by f to the main file, holding a lock on it throughout.
+/// The file is read again under the lock, so the entry is checked against
+/// what is in the file now, not what was there when the program started.
+pub fn append_locked<F>(fname: &Option<String>, rs: &ReadState, f: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&ReadState) -> anyhow::Result<String>,
+{
+    let nm = match fname {
+        Some(nm) => nm,
+        None => {
+            println!("{}", f(rs)?);
+            return Ok(());
+        }
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(nm)?;
+    file.lock()?;
+    let mut s = String::new();
+    file.read_to_string(&mut s)?;
+    let rs = ClockStore::new().read(&s, nm)?;
+    let res = f(&rs).and_then(|ws| {
+        std::io::Write::write_all(&mut file, format!("{}\n", ws).as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    });
+    file.unlock()?;
+    res
+}
+
+/// [clients.<key>] in init.toml, with name, address, rate, currency, tax, terms and round
+pub fn write_invoice<'a, H: clap_conf::Getter<'a, String>>(
+    isub: &clap::ArgMatches,
+    cfg: &'a H,
+    clocks: &ClockStore,
+) -> anyhow::Result<()> {
+    let key = isub.value_of("client").e_str("No client given")?;
+    let get = |k: &str| cfg.grab().conf(format!("clients.{}.{}", key, k)).done();
+    let name = get("name").e_string(format!("No client '{}' in the config", key))?;
+    let client = invoice::Client {
+        name,
+        address: get("address").unwrap_or_default(),
+        rate: get("rate")
+            .e_string(format!("Client '{}' has no rate", key))?
+            .parse()?,
+        currency: get("currency"),
+        tax: match get("tax") {
+            Some(t) => t.parse::<billing::Money>()?.cents(),
+            None => 0,
+        },
+        terms: get("terms"),
+    };
+    let by = match isub.value_of("per") {
+        Some(p) => p.parse()?,
+        None => invoice::ItemsBy::Job,
+    };
+    let rules = match isub.value_of("round").map(String::from).or(get("round")) {
+        Some(r) => vec![rounding::RoundRule {
+            target: None,
+            rounding: r.parse()?,
+        }],
+        None => config_rounding(cfg)?,
     };
+    let state = cfg
+        .grab()
+        .conf("config.invoice_state")
+        .def("{HOME}/.config/work_tock/invoice_number");
+    let state = clap_conf::replace::replace_env(&state).unwrap_or(state);
+
+    let format = match isub.value_of("format") {
+        Some(f) => f.parse()?,
+        None => invoice::InvoiceFormat::Text,
+    };
+    //Build before taking the number, so a bad file does not use one up
+    let mut inv = invoice::build(clocks, &client, by, &rules, 0, moment::today())?;
+    //The number is only used up once the invoice is written
+    invoice::next_number(&state, !isub.is_present("preview"), |n| {
+        inv.number = n;
+        let s = inv.render(format);
+        match isub.value_of("write_file") {
+            Some(fname) => std::fs::write(fname, s)?,
+            None => print!("{}", s),
+        }
+        Ok(())
+    })
+}
 
-    let t_out = STime::now();
-    let t_in = t_out.earlier(duration);
-    write!(ws, "{}\n  -{}\n", t_in, t_out)?;
+/// [[rates]] in init.toml, with job or group, rate, currency and from
+pub fn config_rates<'a, H: clap_conf::Getter<'a, String>>(
+    cfg: &'a H,
+) -> anyhow::Result<Vec<billing::Rate>> {
+    let mut res = Vec::new();
+    for i in 0.. {
+        let get = |k: &str| cfg.grab().conf(format!("rates.{}.{}", i, k)).done();
+        let target = match (get("job"), get("group")) {
+            (Some(j), _) => billing::RateTarget::Job(j),
+            (None, Some(g)) => billing::RateTarget::Group(g),
+            (None, None) => break,
+        };
+        let amount = get("rate")
+            .e_string(format!("Rate {} in the config has no 'rate'", i))?
+            .parse()?;
+        let from = match get("from") {
+            Some(d) => Some(moment::date_from_str(&d, None)?),
+            None => None,
+        };
+        res.push(billing::Rate {
+            target,
+            amount,
+            currency: get("currency"),
+            from,
+        });
+    }
+    Ok(res)
+}
 
-    print_result(fname, &ws)
+/// [[rounding]] in init.toml, with job or group, step, dir and per
+pub fn config_rounding<'a, H: clap_conf::Getter<'a, String>>(
+    cfg: &'a H,
+) -> anyhow::Result<Vec<rounding::RoundRule>> {
+    let mut res = Vec::new();
+    for i in 0.. {
+        let get = |k: &str| cfg.grab().conf(format!("rounding.{}.{}", i, k)).done();
+        let step = match get("step") {
+            Some(s) => s,
+            None => break,
+        };
+        let target = match (get("job"), get("group")) {
+            (Some(j), _) => Some(billing::RateTarget::Job(j)),
+            (None, Some(g)) => Some(billing::RateTarget::Group(g)),
+            (None, None) => None,
+        };
+        let spec: Vec<String> = std::iter::once(step)
+            .chain(get("dir"))
+            .chain(get("per"))
+            .collect();
+        res.push(rounding::RoundRule {
+            target,
+            rounding: spec.join(":").parse()?,
+        });
+    }
+    Ok(res)
 }
 
-pub fn print_result(fname: &Option<String>, s: &str) -> anyhow::Result<()> {
-    match &fname {
-        Some(nm) => {
-            let mut f = std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .append(true)
-                .open(nm)?;
-            std::io::Write::write_fmt(&mut f, format_args!("{}\n", s))?;
+/// [[targets]] in init.toml, with from and hours for mon to sun
+pub fn config_targets<'a, H: clap_conf::Getter<'a, String>>(
+    cfg: &'a H,
+) -> anyhow::Result<Vec<balance::Target>> {
+    const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+    let mut res = Vec::new();
+    for i in 0.. {
+        let get = |k: &str| cfg.grab().conf(format!("targets.{}.{}", i, k)).done();
+        let from = get("from");
+        if from.is_none() && DAYS.iter().all(|d| get(d).is_none()) {
+            break;
         }
-        None => {
-            println!("{}", s);
+        let mut days = [Duration::default(); 7];
+        for (n, d) in DAYS.iter().enumerate() {
+            if let Some(h) = get(d) {
+                days[n] = balance::hours_from_str(&h)?;
+            }
         }
+        let from = match from {
+            Some(d) => Some(moment::date_from_str(&d, None)?),
+            None => None,
+        };
+        res.push(balance::Target { from, days });
     }
-    Ok(())
+    Ok(res)
+}
+
+/// [leave] in init.toml, with days a year for vacation, sick and holiday, and carry_max
+pub fn config_allowances<'a, H: clap_conf::Getter<'a, String>>(
+    cfg: &'a H,
+) -> anyhow::Result<Vec<absence::Allowance>> {
+    let get = |k: &str| cfg.grab().conf(format!("leave.{}", k)).done();
+    let carry_max = match get("carry_max") {
+        Some(c) => Some(absence::halves_from_str(&c)?),
+        None => None,
+    };
+    let mut res = Vec::new();
+    for k in ["vacation", "sick", "holiday"] {
+        if let Some(d) = get(k) {
+            res.push(absence::Allowance {
+                kind: k.parse()?,
+                halves: absence::halves_from_str(&d)?,
+                carry_max,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// [compliance.<name>] in init.toml, with max_day, min_rest and breaks
+pub fn config_compliance<'a, H: clap_conf::Getter<'a, String>>(
+    csub: &clap::ArgMatches,
+    cfg: &'a H,
+) -> anyhow::Result<compliance::Rules> {
+    let name = match csub.value_of("rules") {
+        Some(r) => r.to_string(),
+        None => cfg.grab().conf("compliance.rules").def("de"),
+    };
+    let get = |k: &str| cfg.grab().conf(format!("compliance.{}.{}", name, k)).done();
+    let (max_day, min_rest, breaks) = (get("max_day"), get("min_rest"), get("breaks"));
+    if max_day.is_none() && min_rest.is_none() && breaks.is_none() {
+        return match name.as_str() {
+            "de" => Ok(compliance::Rules::de()),
+            _ => e_string(format!(
+                "No rules '{}' under [compliance] in the config",
+                name
+            )),
+        };
+    }
+    Ok(compliance::Rules {
+        max_day: max_day.map(|h| balance::hours_from_str(&h)).transpose()?,
+        min_rest: min_rest.map(|h| balance::hours_from_str(&h)).transpose()?,
+        breaks: match breaks {
+            Some(b) => compliance::breaks_from_str(&b)?,
+            None => Vec::new(),
+        },
+    })
 }
 
 pub fn history_list<'a, H: clap_conf::Getter<'a, String>>(cfg: &'a H) -> Vec<String> {
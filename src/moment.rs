@@ -2,12 +2,14 @@ use crate::err::{self, ErrType};
 use chrono::naive::NaiveDate;
 use chrono::offset::Local;
 use chrono::{Datelike, Timelike, Weekday};
-use derive_more::*;
+use derive_more::{Neg, Sum};
+use serde::{Serialize, Serializer};
 use std::cmp::{Ordering, PartialOrd};
 use std::fmt::{Debug, Display, Formatter};
+use std::ops::{Add, AddAssign, Sub, SubAssign};
 use std::str::FromStr;
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
 pub struct Moment {
     pub t: STime,
     pub d: NaiveDate,
@@ -17,18 +19,34 @@ impl Moment {
     pub fn now() -> Self {
         let now = Local::now();
         Moment {
-            t: STime::new(now.time().hour() as u32, now.time().minute() as u32),
+            t: STime::new(now.time().hour(), now.time().minute()),
             d: now.date().naive_local(),
         }
     }
     pub fn new(d: NaiveDate, t: STime) -> Self {
         Moment { d, t }
     }
+
+    pub fn rolled(d: NaiveDate, t: STime) -> Option<Self> {
+        Moment::new(d, STime::new(0, 0)).checked_add(t.since_midnight())
+    }
+
+    pub fn checked_add(&self, dur: Duration) -> Option<Self> {
+        let day = 24 * 60;
+        let m = self.t.since_midnight().checked_add(dur)?.mins();
+        let d = self.d.checked_add_signed(chrono_days(m.div_euclid(day))?)?;
+        Some(Moment::new(d, STime::new(0, m.rem_euclid(day) as u32)))
+    }
+
+    pub fn checked_sub(&self, dur: Duration) -> Option<Self> {
+        self.checked_add(-dur)
+    }
+
     pub fn print_relative(&self, now: &Moment) -> String {
         match self.d {
             d if d == now.d => format!("today : {}", self.t),
             d if d + chrono::Duration::days(1) == now.d => format!("yesterday : {}", self.t),
-            d => format!("{} : {}", d.format("&d/%m/%Y"), self.t),
+            d => format!("{} : {}", d.format("%d/%m/%Y"), self.t),
         }
     }
 
@@ -36,9 +54,8 @@ impl Moment {
         self.print_relative(&Self::now())
     }
 
-    pub fn time_since(&self, prev: &Moment) -> STime {
-        let days_between = (self.d - prev.d).num_days() as u32;
-        (STime::new(24 * days_between, 0) + self.t) - prev.t
+    pub fn time_since(&self, prev: &Moment) -> Duration {
+        Duration::days((self.d - prev.d).num_days()) + self.t.since(prev.t)
     }
 }
 
@@ -52,33 +69,44 @@ impl Ord for Moment {
     fn cmp(&self, b: &Self) -> Ordering {
         match self.d.cmp(&b.d) {
             Ordering::Equal => self.t.cmp(&b.t),
-            n => return n,
+            n => n,
         }
     }
 }
 
-#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Add, Sub, AddAssign, SubAssign)]
+/// Times past 24:00 are allowed, for clocks that run into the next day.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub struct STime(u32); //minutes
 
 impl STime {
     pub fn new(hr: u32, min: u32) -> Self {
         STime(hr * 60 + min)
     }
+    pub fn mins(&self) -> u32 {
+        self.0
+    }
+
     pub fn now() -> Self {
         let t = Local::now();
-        STime::new(t.time().hour() as u32, t.time().minute() as u32)
+        STime::new(t.time().hour(), t.time().minute())
     }
 
-    pub fn since(&self, now_date: &NaiveDate, then_time: Self, then_date: &NaiveDate) -> Self {
-        let days_between = (*now_date - *then_date).num_days() as u32;
-        *self + STime::new(24 * days_between, 0) - then_time
+    pub fn checked_new(hr: u32, min: u32) -> Option<Self> {
+        hr.checked_mul(60)?.checked_add(min).map(STime)
     }
 
-    pub fn earlier(&self, b: Self) -> Self {
-        if b.0 > self.0 {
-            return Self::new(0, 0);
-        }
-        Self(self.0 - b.0)
+    pub fn since_midnight(&self) -> Duration {
+        Duration(self.0 as i64)
+    }
+
+    pub fn since(&self, then: Self) -> Duration {
+        Duration(self.0 as i64 - then.0 as i64)
+    }
+}
+
+impl Serialize for STime {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
     }
 }
 
@@ -91,7 +119,118 @@ impl FromStr for STime {
         if min >= 60 {
             return Err(err::ErrType::MinutesOver60.into());
         }
-        Ok(STime(hr * 60 + min))
+        STime::checked_new(hr, min).ok_or_else(|| err::ErrType::NotATime.into())
+    }
+}
+
+/// A signed length of time in minutes, kept apart from STime so that
+/// taking one time from another cannot underflow.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Default, Neg, Sum)]
+pub struct Duration(i64); //minutes
+
+/// The longest duration taken from the user, the same as the longest STime
+pub const MAX_MINS: i64 = u32::MAX as i64;
+
+impl Duration {
+    pub fn new(hr: i64, min: i64) -> Self {
+        Duration(hr * 60 + min)
+    }
+    pub fn checked_new(hr: i64, min: i64) -> Option<Self> {
+        let m = hr.checked_mul(60)?.checked_add(min)?;
+        match m.checked_abs()? <= MAX_MINS {
+            true => Some(Duration(m)),
+            false => None,
+        }
+    }
+    pub fn days(n: i64) -> Self {
+        Duration(n * 24 * 60)
+    }
+    pub fn mins(&self) -> i64 {
+        self.0
+    }
+    pub fn checked_add(&self, b: Self) -> Option<Self> {
+        self.0.checked_add(b.0).map(Duration)
+    }
+    pub fn checked_sub(&self, b: Self) -> Option<Self> {
+        self.0.checked_sub(b.0).map(Duration)
+    }
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+}
+
+/// Saturates rather than panic. Durations from the user are checked against MAX_MINS,
+/// so totals never get near the limits.
+impl Add for Duration {
+    type Output = Self;
+    fn add(self, b: Self) -> Self {
+        Duration(self.0.saturating_add(b.0))
+    }
+}
+
+impl Sub for Duration {
+    type Output = Self;
+    fn sub(self, b: Self) -> Self {
+        Duration(self.0.saturating_sub(b.0))
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, b: Self) {
+        *self = *self + b;
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, b: Self) {
+        *self = *self - b;
+    }
+}
+
+impl Serialize for Duration {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// Durations as in "1:30", "1h30m", "90m", "1.5h" or "2h", with an optional sign
+impl FromStr for Duration {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let (neg, s) = match s.strip_prefix('-') {
+            Some(r) => (true, r),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let t = match s.contains(':') {
+            true => STime::from_str(s)?.since_midnight(),
+            false => units_from_str(s)?,
+        };
+        Ok(if neg { -t } else { t })
+    }
+}
+
+fn units_from_str(s: &str) -> anyhow::Result<Duration> {
+    let bad = || anyhow::anyhow!("'{}' is not a duration, as in 1h30m, 90m, 1.5h or 1:30", s);
+    let mut mins = 0.;
+    let mut num = String::new();
+    for c in s.chars() {
+        match c {
+            '0'..='9' | '.' => num.push(c),
+            'h' | 'm' => {
+                let n: f64 = num.parse().map_err(|_| bad())?;
+                mins += match c {
+                    'h' => n * 60.,
+                    _ => n,
+                };
+                num.clear();
+            }
+            _ => return Err(bad()),
+        }
+    }
+    match num.is_empty() && !s.is_empty() {
+        true => Duration::checked_new(0, mins.round() as i64).ok_or_else(bad),
+        false => Err(bad()),
     }
 }
 
@@ -112,6 +251,114 @@ pub fn date_from_str(s: &str, def_year: Option<i32>) -> anyhow::Result<NaiveDate
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimeExpr {
+    At(STime),
+    Offset(Duration),
+}
+
+impl TimeExpr {
+    /// The moment on the date given, where offsets count from the time now on that date
+    pub fn on(&self, d: NaiveDate, now: Moment) -> anyhow::Result<Moment> {
+        match self {
+            TimeExpr::At(t) => Ok(Moment::new(d, *t)),
+            TimeExpr::Offset(dur) => Moment::new(d, now.t)
+                .checked_add(*dur)
+                .filter(|m| writable(m.d))
+                .ok_or_else(|| ErrType::NotATime.into()),
+        }
+    }
+}
+
+/// Times as in "14:30", "14.30", "14", "9am", "2:15pm", "now", "-15m" or "+1h30m"
+impl FromStr for TimeExpr {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim().to_lowercase();
+        if s == "now" {
+            return Ok(TimeExpr::Offset(Duration::default()));
+        }
+        if s.starts_with('-') || s.starts_with('+') {
+            return Ok(TimeExpr::Offset(Duration::from_str(&s)?));
+        }
+        let (s, pm) = match (s.strip_suffix("am"), s.strip_suffix("pm")) {
+            (Some(r), _) => (r.trim(), Some(false)),
+            (_, Some(r)) => (r.trim(), Some(true)),
+            _ => (s.as_str(), None),
+        };
+        let s = s.replace('.', ":");
+        let t = match s.contains(':') {
+            true => STime::from_str(&s)?,
+            false => STime::checked_new(s.parse()?, 0).ok_or(ErrType::NotATime)?,
+        };
+        match pm {
+            None => Ok(TimeExpr::At(t)),
+            Some(pm) => {
+                let (hr, min) = (t.mins() / 60, t.mins() % 60);
+                if hr == 0 || hr > 12 {
+                    return Err(ErrType::NotATime.into());
+                }
+                let hr = match pm {
+                    true => hr % 12 + 12,
+                    false => hr % 12,
+                };
+                Ok(TimeExpr::At(STime::new(hr, min)))
+            }
+        }
+    }
+}
+
+/// Dates as in "today", "yesterday", "tomorrow", "mon" or "monday" for the latest one
+/// up to today, "-2d", "+1w", "2026-10-17", "17/10/2026" or "17/10" in this year
+pub fn date_expr(s: &str, today: NaiveDate) -> anyhow::Result<NaiveDate> {
+    match parse_date_expr(&s.trim().to_lowercase(), today)? {
+        Some(d) if writable(d) => Ok(d),
+        _ => Err(ErrType::DateNotValid.into()),
+    }
+}
+
+fn parse_date_expr(s: &str, today: NaiveDate) -> anyhow::Result<Option<NaiveDate>> {
+    match s {
+        "today" => return Ok(Some(today)),
+        "yesterday" => return Ok(today.pred_opt()),
+        "tomorrow" => return Ok(today.succ_opt()),
+        _ => {}
+    }
+    if let Ok(wd) = s.parse::<Weekday>() {
+        let back = (today.weekday().num_days_from_monday() + 7 - wd.num_days_from_monday()) % 7;
+        return Ok(today.checked_sub_signed(chrono::Duration::days(back as i64)));
+    }
+    if let Some(days) = date_offset(s) {
+        return Ok(days
+            .and_then(chrono_days)
+            .and_then(|d| today.checked_add_signed(d)));
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(Some(d));
+    }
+    date_from_str(s, Some(today.year())).map(Some)
+}
+
+/// Some(None) if the offset is too big to count in days
+fn date_offset(s: &str) -> Option<Option<i64>> {
+    let sign = match s.chars().next()? {
+        '-' => -1,
+        '+' => 1,
+        _ => return None,
+    };
+    let (n, per) = match s[1..].strip_suffix('w') {
+        Some(n) => (n, 7),
+        None => (s[1..].strip_suffix('d')?, 1),
+    };
+    let n: i64 = n.parse().ok()?;
+    Some(n.checked_mul(sign * per))
+}
+
+/// Dates the tock format can write and read back
+fn writable(d: NaiveDate) -> bool {
+    (0..=9999).contains(&d.year())
+}
+
 pub fn week_yr_from_str(s: &str, def_year: Option<i32>) -> anyhow::Result<NaiveDate> {
     let mut ss = s.split("/");
     let dd: u32 = num_from_split(&mut ss)?;
@@ -157,6 +404,14 @@ pub fn month_yr_from_str(s: &str, def_year: Option<i32>) -> anyhow::Result<Naive
     }
 }
 
+/// chrono::Duration::days panics out of its range, which is far past any NaiveDate
+fn chrono_days(n: i64) -> Option<chrono::Duration> {
+    match n.checked_abs()? < 1 << 32 {
+        true => Some(chrono::Duration::days(n)),
+        false => None,
+    }
+}
+
 pub fn today() -> NaiveDate {
     chrono::offset::Local::today().naive_local()
 }
@@ -179,7 +434,21 @@ impl Debug for STime {
 
 impl Display for STime {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        f.pad(&format!("{:?}", self))
+    }
+}
+
+impl Debug for Duration {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let m = self.0.unsigned_abs();
+        write!(f, "{}{:02}:{:02}", sign, m / 60, m % 60)
+    }
+}
+
+impl Display for Duration {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.pad(&format!("{:?}", self))
     }
 }
 
@@ -191,6 +460,73 @@ mod test {
         assert!("243430343090349309309430334390:54"
             .parse::<STime>()
             .is_err());
-        assert_eq!("24:54".parse(), Ok(STime::new(24, 54)));
+        assert_eq!("24:54".parse::<STime>().unwrap(), STime::new(24, 54));
+        assert!("99999999:00".parse::<STime>().is_err());
+    }
+
+    #[test]
+    pub fn test_duration_is_signed() {
+        let a = Moment::new(NaiveDate::from_ymd(2021, 11, 2), STime::new(1, 0));
+        let b = Moment::new(NaiveDate::from_ymd(2021, 11, 1), STime::new(23, 30));
+        assert_eq!(a.time_since(&b), Duration::new(1, 30));
+        assert_eq!(b.time_since(&a), Duration::new(-1, -30));
+        assert_eq!(format!("{}", b.time_since(&a)), "-01:30");
+        assert_eq!(a.checked_sub(Duration::new(1, 30)), Some(b));
+        assert_eq!(
+            Moment::rolled(b.d, STime::new(25, 0)),
+            Some(Moment::new(a.d, STime::new(1, 0)))
+        );
+        assert_eq!("-0:45".parse::<Duration>().unwrap(), Duration::new(0, -45));
+        assert_eq!(a.checked_add(Duration(i64::MAX)), None);
+        assert_eq!(a.checked_add(Duration::days(1 << 40)), None);
+        assert_eq!(Duration::checked_new(MAX_MINS, 0), None);
+        let d = |s: &str| s.parse::<Duration>().unwrap();
+        assert_eq!(d("1h30m"), Duration::new(1, 30));
+        assert_eq!(d("90m"), Duration::new(1, 30));
+        assert_eq!(d("1.5h"), Duration::new(1, 30));
+        assert_eq!(d("2h"), Duration::new(2, 0));
+        assert_eq!(d("-0.25h"), Duration::new(0, -15));
+        assert!("2".parse::<Duration>().is_err());
+        assert!("1h30".parse::<Duration>().is_err());
+        assert!("--1h".parse::<Duration>().is_err());
+    }
+
+    #[test]
+    pub fn test_time_and_date_exprs() {
+        let now = Moment::new(NaiveDate::from_ymd(2026, 10, 17), STime::new(0, 10));
+        let at = |s: &str| s.parse::<TimeExpr>().unwrap().on(now.d, now).unwrap();
+        assert_eq!(at("9am"), Moment::new(now.d, STime::new(9, 0)));
+        assert_eq!(at("12:15am").t, STime::new(0, 15));
+        assert_eq!(at("2.30pm").t, STime::new(14, 30));
+        assert_eq!(at("14.30").t, STime::new(14, 30));
+        assert_eq!(at("14").t, STime::new(14, 0));
+        assert_eq!(at("now"), now);
+        //Back over midnight
+        assert_eq!(
+            at("-15m"),
+            Moment::new(NaiveDate::from_ymd(2026, 10, 16), STime::new(23, 55))
+        );
+        assert_eq!(at("+1h30m").t, STime::new(1, 40));
+        assert!("13pm".parse::<TimeExpr>().is_err());
+        assert!("-15".parse::<TimeExpr>().is_err());
+
+        //17/10/2026 is a Saturday
+        let d = |s: &str| date_expr(s, now.d).unwrap();
+        assert_eq!(d("yesterday"), NaiveDate::from_ymd(2026, 10, 16));
+        assert_eq!(d("mon"), NaiveDate::from_ymd(2026, 10, 12));
+        assert_eq!(d("Saturday"), now.d);
+        assert_eq!(d("-2d"), NaiveDate::from_ymd(2026, 10, 15));
+        assert_eq!(d("+1w"), NaiveDate::from_ymd(2026, 10, 24));
+        assert_eq!(d("2026-01-02"), NaiveDate::from_ymd(2026, 1, 2));
+        assert_eq!(d("2/1"), NaiveDate::from_ymd(2026, 1, 2));
+        assert!(date_expr("someday", now.d).is_err());
+        assert!(date_expr("+2000000000000000000w", now.d).is_err());
+        assert!(date_expr("-200000000000000d", now.d).is_err());
+        assert!(date_expr("1/1/-5", now.d).is_err());
+        assert!("-4000000000m"
+            .parse::<TimeExpr>()
+            .unwrap()
+            .on(now.d, now)
+            .is_err());
     }
 }
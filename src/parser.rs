@@ -1,6 +1,10 @@
+use crate::absence::AbsenceKind;
+use crate::billing::{Money, RateTarget};
 use crate::err::*;
 use crate::moment::STime;
 use crate::tokenize::{self, Token, TokenType};
+use std::borrow::Cow;
+use std::collections::VecDeque;
 
 pub type ActionRes<'a> = Result<Action<'a>, ParseErr>;
 
@@ -26,18 +30,25 @@ pub enum ActionData<'a> {
     Group(String, Vec<String>),
     ShortDate(u32, u32),
     LongDate(u32, u32, i32),
-    SetJob(&'a str),
+    SetJob(Cow<'a, str>),
     SetYear(i32),
     ClearTags,
-    ClearTag(&'a str),
-    Tag(&'a str),
+    ClearTag(Cow<'a, str>),
+    Tag(Cow<'a, str>, Option<String>),
     Clockin(STime),
     Clockout(STime),
+    ClockoutOn(u32, u32, Option<i32>, STime),
+    Rate(RateTarget, Money, Option<String>),
+    Note(String),
+    Break,
+    /// A day off on the current date, true if only half a day, with a note
+    Absence(AbsenceKind, bool, Option<String>),
+    Comment(&'a str),
     End,
 }
 
 impl<'a> ActionData<'a> {
-    fn as_action(self, tk: &Token<'a>) -> Action<'a> {
+    fn into_action(self, tk: &Token<'a>) -> Action<'a> {
         Action {
             line: tk.line,
             col: tk.col,
@@ -49,6 +60,7 @@ impl<'a> ActionData<'a> {
 pub struct Parser<'a> {
     tk: tokenize::Tokenizer<'a>,
     next: Option<Token<'a>>,
+    comments: VecDeque<Token<'a>>,
 }
 
 impl<'a> Parser<'a> {
@@ -56,13 +68,20 @@ impl<'a> Parser<'a> {
         Parser {
             tk: tokenize::Tokenizer::new(s),
             next: None,
+            comments: VecDeque::new(),
         }
     }
 
     fn next_token(&mut self) -> tokenize::TokenRes<'a> {
-        match self.next.take() {
-            Some(t) => Ok(t),
-            None => self.tk.next_token(),
+        loop {
+            let t = match self.next.take() {
+                Some(t) => t,
+                None => self.tk.next_token()?,
+            };
+            match t.tt {
+                TokenType::Comment => self.comments.push_back(t),
+                _ => return Ok(t),
+            }
         }
     }
 
@@ -85,36 +104,68 @@ impl<'a> Parser<'a> {
         }
     }
 
-    pub fn next_ident(&mut self) -> Result<Option<&'a str>, ParseErr> {
+    pub fn recover(&mut self, line: usize) {
+        if let Some(t) = self.next.take() {
+            if t.line > line {
+                self.next = Some(t);
+                return;
+            }
+        }
+        while self.tk.line() <= line {
+            match self.tk.next_token() {
+                Ok(t) if t.tt == TokenType::EOF => return,
+                Ok(t) if t.tt == TokenType::Comment => self.comments.push_back(t),
+                _ => {}
+            }
+        }
+    }
+
+    fn time_val(&self, hr: &Token<'a>, min: &Token<'a>) -> Result<STime, ParseErr> {
+        let m = min.num_val()?;
+        if m >= 60 {
+            return Err(min.as_err(ErrType::MinutesOver60));
+        }
+        STime::checked_new(hr.num_val()?, m).ok_or_else(|| hr.as_err(ErrType::NotATime))
+    }
+
+    pub fn next_ident(&mut self) -> Result<Option<Cow<'a, str>>, ParseErr> {
         loop {
             let t = self.next_token()?;
             match t.tt {
                 TokenType::EOF => return Ok(None),
-                TokenType::Ident => return Ok(Some(t.s)),
+                TokenType::Ident => return self.job_path(&t).map(Some),
                 _ => {}
             }
         }
     }
 
     pub fn next_action(&mut self) -> ActionRes<'a> {
-        let mut t = self.next_token()?;
-        while t.tt == TokenType::Sep {
-            t = self.tk.next_token()?;
-        }
+        let t = loop {
+            let t = self.next_token()?;
+            if let Some(c) = self.comments.pop_front() {
+                self.next = Some(t);
+                return Ok(ActionData::Comment(c.s).into_action(&c));
+            }
+            if t.tt != TokenType::Sep {
+                break t;
+            }
+        };
 
         match t.tt {
             TokenType::Number => self.from_number(t),
-            TokenType::EOF => Ok(ActionData::End.as_action(&t)),
-            TokenType::Minus => self.clock_out(),
+            TokenType::EOF => Ok(ActionData::End.into_action(&t)),
+            TokenType::Minus => self.clock_out(t),
             TokenType::Ident => self.from_ident(t),
-            TokenType::Tag => {
-                Ok(ActionData::Tag(self.next_token_as(TokenType::Ident)?.s).as_action(&t))
-            }
+            TokenType::Tag => self.tag(t),
             TokenType::ClearTag => match self.try_next_token(|t| t.tt == TokenType::Ident) {
-                Some(nt) => Ok(ActionData::ClearTag(nt.s).as_action(&t)),
-                None => Ok(ActionData::ClearTags.as_action(&t)),
+                Some(nt) => Ok(ActionData::ClearTag(nt.ident_val()).into_action(&t)),
+                None => Ok(ActionData::ClearTags.into_action(&t)),
             },
+            TokenType::Note => Ok(ActionData::Note(t.unquote()).into_action(&t)),
             TokenType::Dollar => self.group(),
+            TokenType::At => self.rate(t),
+            TokenType::Bang => self.absence(t),
+            TokenType::Tilde => Ok(ActionData::Break.into_action(&t)),
             _ => Err(t.as_err(ErrType::NotAnItem)),
         }
     }
@@ -126,29 +177,124 @@ impl<'a> Parser<'a> {
         }
         let num2 = self.next_token()?;
         if delim1.tt == TokenType::Colon {
-            return Ok(
-                ActionData::Clockin(STime::new(num1.num_val()?, num2.num_val()?)).as_action(&num1),
-            );
+            return Ok(ActionData::Clockin(self.time_val(&num1, &num2)?).into_action(&num1));
         }
         let _delim2 = match self.try_next_token(|t| t.tt == TokenType::Slash) {
             Some(s) => s,
             None => {
-                return Ok(ActionData::ShortDate(num1.num_val()?, num2.num_val()?).as_action(&num1))
+                return Ok(
+                    ActionData::ShortDate(num1.num_val()?, num2.num_val()?).into_action(&num1)
+                )
             }
         };
-        let num3 = self.tk.next_token()?;
+        let num3 = self.next_token()?;
         Ok(
             ActionData::LongDate(num1.num_val()?, num2.num_val()?, num3.num_val()? as i32)
-                .as_action(&num1),
+                .into_action(&num1),
         )
     }
 
-    /// Follows a '-'
-    pub fn clock_out(&mut self) -> ActionRes<'a> {
+    /// Follows a '-', either "-HH:MM" or with a date "-dd/mm[/yyyy] HH:MM"
+    pub fn clock_out(&mut self, minus: Token<'a>) -> ActionRes<'a> {
         let num1 = self.next_token_as(TokenType::Number)?;
-        let _ = self.next_token_as(TokenType::Colon)?;
-        let num2 = self.next_token_as(TokenType::Number)?;
-        Ok(ActionData::Clockout(STime::new(num1.num_val()?, num2.num_val()?)).as_action(&num1))
+        let delim = self.next_token()?;
+        match delim.tt {
+            TokenType::Colon => {
+                let num2 = self.next_token_as(TokenType::Number)?;
+                Ok(ActionData::Clockout(self.time_val(&num1, &num2)?).into_action(&minus))
+            }
+            TokenType::Slash => {
+                let mm = self.next_token_as(TokenType::Number)?;
+                let yy = match self.try_next_token(|t| t.tt == TokenType::Slash) {
+                    Some(_) => Some(self.next_token_as(TokenType::Number)?.num_val()? as i32),
+                    None => None,
+                };
+                let hr = self.next_token_as(TokenType::Number)?;
+                self.next_token_as(TokenType::Colon)?;
+                let min = self.next_token_as(TokenType::Number)?;
+                let t = self.time_val(&hr, &min)?;
+                Ok(
+                    ActionData::ClockoutOn(num1.num_val()?, mm.num_val()?, yy, t)
+                        .into_action(&minus),
+                )
+            }
+            _ => Err(delim.as_err(ErrType::NotSlashOrColon)),
+        }
+    }
+
+    pub fn tag(&mut self, us: Token<'a>) -> ActionRes<'a> {
+        let key = self.next_token_as(TokenType::Ident)?.ident_val();
+        let value = match self.try_next_token(|t| t.tt == TokenType::Equals) {
+            Some(_) => Some(self.tag_value()?),
+            None => None,
+        };
+        Ok(ActionData::Tag(key, value).into_action(&us))
+    }
+
+    fn tag_value(&mut self) -> Result<String, ParseErr> {
+        let is_part = |t: &Token| {
+            matches!(
+                t.tt,
+                TokenType::Ident | TokenType::Number | TokenType::Minus
+            ) && !t.s.starts_with('\'')
+        };
+        let first = self.next_token()?;
+        if first.s.starts_with('\'') && first.tt == TokenType::Ident {
+            return Ok(first.unquote());
+        }
+        if !is_part(&first) {
+            return Err(first.as_err(ErrType::Expected(TokenType::Ident)));
+        }
+        let mut res = first.s.to_string();
+        let mut end = (first.line, first.col + first.s.chars().count());
+        while let Some(t) = self.try_next_token(|t| is_part(t) && (t.line, t.col) == end) {
+            res.push_str(t.s);
+            end = (t.line, t.col + t.s.chars().count());
+        }
+        Ok(res)
+    }
+
+    /// Follows a '@', as in "@client/project 80.50 GBP" or "@$group 60"
+    pub fn rate(&mut self, at: Token<'a>) -> ActionRes<'a> {
+        let target = match self.try_next_token(|t| t.tt == TokenType::Dollar) {
+            Some(_) => {
+                let name = self.next_token_as(TokenType::Ident)?;
+                RateTarget::Group(name.ident_val().into_owned())
+            }
+            None => {
+                let job = self.next_token_as(TokenType::Ident)?;
+                RateTarget::Job(self.job_path(&job)?.into_owned())
+            }
+        };
+        let whole = self.next_token_as(TokenType::Number)?;
+        let mut amount = whole.s.to_string();
+        if self.try_next_token(|t| t.tt == TokenType::Dot).is_some() {
+            amount.push('.');
+            amount.push_str(self.next_token_as(TokenType::Number)?.s);
+        }
+        let amount = amount
+            .parse()
+            .map_err(|_| whole.as_err(ErrType::NotAnAmount))?;
+        let currency = self
+            .try_next_token(|t| t.tt == TokenType::Ident)
+            .map(|t| t.ident_val().into_owned());
+        Ok(ActionData::Rate(target, amount, currency).into_action(&at))
+    }
+
+    /// Follows a '!', as in "!vacation", "!sick half" or "!holiday \"New Year\""
+    pub fn absence(&mut self, bang: Token<'a>) -> ActionRes<'a> {
+        let kind = self.next_token_as(TokenType::Ident)?;
+        let kind = kind
+            .s
+            .parse()
+            .map_err(|_| kind.as_err(ErrType::NotAnAbsence))?;
+        let half = self
+            .try_next_token(|t| t.tt == TokenType::Ident && t.s == "half")
+            .is_some();
+        let note = self
+            .try_next_token(|t| t.tt == TokenType::Note)
+            .map(|t| t.unquote());
+        Ok(ActionData::Absence(kind, half, note).into_action(&bang))
     }
 
     pub fn group(&mut self) -> ActionRes<'a> {
@@ -158,10 +304,11 @@ impl<'a> Parser<'a> {
         loop {
             let next_member = self.next_token()?;
             match next_member.tt {
-                TokenType::Ident => members.push(next_member.s.to_string()),
+                TokenType::Ident => members.push(self.job_path(&next_member)?.into_owned()),
                 TokenType::Sep => {}
                 TokenType::SquareClose => {
-                    return Ok(ActionData::Group(name.s.to_string(), members).as_action(&name))
+                    return Ok(ActionData::Group(name.ident_val().into_owned(), members)
+                        .into_action(&name))
                 }
                 TokenType::EOF => return Err(next_member.as_err(ErrType::UnexpectedEOF)),
                 _ => return Err(next_member.as_err(ErrType::Expected(TokenType::Ident))),
@@ -174,11 +321,22 @@ impl<'a> Parser<'a> {
             match t.s {
                 "year" => {
                     let yr = self.next_token_as(TokenType::Number)?;
-                    return Ok(ActionData::SetYear(yr.num_val()? as i32).as_action(&t));
+                    return Ok(ActionData::SetYear(yr.num_val()? as i32).into_action(&t));
                 }
                 _ => return Err(eq.as_err(ErrType::NotYear)),
             }
         }
-        Ok(ActionData::SetJob(t.s).as_action(&t))
+        Ok(ActionData::SetJob(self.job_path(&t)?).into_action(&t))
+    }
+
+    pub fn job_path(&mut self, first: &Token<'a>) -> Result<Cow<'a, str>, ParseErr> {
+        let mut res = first.ident_val();
+        while self.try_next_token(|t| t.tt == TokenType::Slash).is_some() {
+            let part = self.next_token_as(TokenType::Ident)?;
+            let r = res.to_mut();
+            r.push('/');
+            r.push_str(&part.ident_val());
+        }
+        Ok(res)
     }
 }
@@ -1,22 +1,151 @@
-use crate::err::{ClockErr, ClockErrType, ErrType, ParseErr};
-use crate::moment::{Moment, STime};
-use crate::parser::{ActionData, Parser};
+use crate::absence::Absence;
+use crate::billing::Rate;
+use crate::err::{ClockErr, ClockErrType, ErrType, ParseErr, ParseErrs};
+use crate::moment::{Duration, Moment, STime};
+use crate::parser::{Action, ActionData, Parser};
+use crate::rounding::{self, RoundRule, Totals};
 use chrono::naive::NaiveDate;
+use chrono::Datelike;
+use serde::Serialize;
 use std::cmp::{Ord, Ordering, PartialOrd};
 use std::collections::BTreeMap;
-//use std::fmt::{self, Display};
+use std::fmt::{self, Display};
+use std::sync::Arc;
 
+#[derive(Debug, PartialEq, Serialize)]
 pub struct Group {
     pub name: String,
     pub members: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tag {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl Tag {
+    pub fn new(key: &str, value: Option<&str>) -> Self {
+        Tag {
+            key: key.to_string(),
+            value: value.map(String::from),
+        }
+    }
+
+    pub fn set(self, tags: &mut Vec<Tag>) {
+        match tags.iter_mut().find(|t| t.key == self.key) {
+            Some(t) => t.value = self.value,
+            None => tags.push(self),
+        }
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(v) => write!(f, "{}={}", self.key, v),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+impl Serialize for Tag {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SrcLoc {
+    pub file: Arc<str>,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for SrcLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Clock {
     pub c_in: Moment,
-    pub c_out: STime,
+    pub c_out: Moment,
     pub job: String,
-    pub tags: Vec<String>,
+    pub tags: Vec<Tag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip)]
+    pub loc: SrcLoc,
+    /// Where the clockout was read, or the next clockin that ended this one
+    #[serde(skip)]
+    pub out_loc: SrcLoc,
+}
+
+impl Clock {
+    fn start_mins(&self) -> i64 {
+        self.c_in.d.num_days_from_ce() as i64 * 24 * 60 + self.c_in.t.mins() as i64
+    }
+
+    fn end_mins(&self) -> i64 {
+        self.c_out.d.num_days_from_ce() as i64 * 24 * 60 + self.c_out.t.mins() as i64
+    }
+
+    pub fn times(&self) -> String {
+        match (self.c_out.d - self.c_in.d).num_days() {
+            0 => format!("{}-{}", self.c_in.t, self.c_out.t),
+            n => format!("{}-{}({:+})", self.c_in.t, self.c_out.t, n),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.job,
+            self.c_in.d.format("%d/%m/%Y"),
+            self.times()
+        )
+    }
+
+    pub fn note_str(&self) -> String {
+        match &self.note {
+            Some(n) => format!("   {}", n),
+            None => String::new(),
+        }
+    }
+
+    pub fn lines(&self) -> String {
+        match self.out_loc.line == self.loc.line {
+            true => format!("line {}", self.loc.line),
+            false => format!("lines {}-{}", self.loc.line, self.out_loc.line),
+        }
+    }
+
+    pub fn duration(&self) -> Result<Duration, ClockErr> {
+        if self.c_in > self.c_out {
+            return Err(ClockErr {
+                clock: Box::new(self.clone()),
+                etype: ClockErrType::OutBeforeIn,
+            });
+        }
+        Ok(self.c_out.time_since(&self.c_in))
+    }
+
+    pub fn day_parts(&self) -> Result<Vec<(NaiveDate, Duration)>, ClockErr> {
+        self.duration()?;
+        let mut res = Vec::new();
+        let mut start = self.c_in;
+        while start.d < self.c_out.d {
+            let next = Moment::new(start.d + chrono::Duration::days(1), STime::new(0, 0));
+            res.push((start.d, next.time_since(&start)));
+            start = next;
+        }
+        if self.c_out > start || res.is_empty() {
+            res.push((start.d, self.c_out.time_since(&start)));
+        }
+        Ok(res)
+    }
 }
 
 impl Ord for Clock {
@@ -33,31 +162,90 @@ impl Ord for Clock {
             Ordering::Equal => {}
             o => return o,
         }
-        self.tags.cmp(&b.tags)
+        match self.tags.cmp(&b.tags) {
+            Ordering::Equal => {}
+            o => return o,
+        }
+        self.note.cmp(&b.note)
+    }
+}
+
+/// Clocks are equal if they describe the same time, wherever they were read from
+impl PartialEq for Clock {
+    fn eq(&self, b: &Self) -> bool {
+        self.cmp(b) == Ordering::Equal
     }
 }
 
+impl Eq for Clock {}
+
 impl PartialOrd for Clock {
     fn partial_cmp(&self, b: &Self) -> Option<Ordering> {
         Some(self.cmp(b))
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollisionType {
+    Duplicate,
+    Overlap,
+    RunsInto,
+}
+
+#[derive(Debug)]
+pub struct Collision {
+    pub a: Clock,
+    pub b: Clock,
+    pub ctype: CollisionType,
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (desc, main, other) = match self.ctype {
+            CollisionType::Duplicate => ("Duplicate clock", &self.b, &self.a),
+            CollisionType::Overlap => ("Clock overlaps another", &self.b, &self.a),
+            CollisionType::RunsInto => ("Clock runs into the next", &self.a, &self.b),
+        };
+        writeln!(
+            f,
+            "{}: {} : {} ({})",
+            main.loc,
+            desc,
+            main.describe(),
+            main.lines()
+        )?;
+        write!(
+            f,
+            "  {}: collides with : {} ({})",
+            other.loc,
+            other.describe(),
+            other.lines()
+        )
+    }
+}
+
 //Half a clock
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Clockin {
     pub c_in: Moment,
     pub job: String,
-    tags: Vec<String>,
+    pub tags: Vec<Tag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip)]
+    pub loc: SrcLoc,
 }
 
 impl Clockin {
-    pub fn as_clock(self, c_out: STime) -> Clock {
+    pub fn as_clock(self, c_out: Moment, out_loc: SrcLoc) -> Clock {
         Clock {
             c_in: self.c_in,
             c_out,
             job: self.job,
             tags: self.tags,
+            note: self.note,
+            loc: self.loc,
+            out_loc,
         }
     }
     pub fn print(&self) {
@@ -71,17 +259,45 @@ impl Clockin {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Break {
+    pub start: Moment,
+    pub end: Option<Moment>,
+}
+
+impl Break {
+    pub fn duration(&self) -> Duration {
+        match self.end {
+            Some(e) => e.time_since(&self.start),
+            None => Duration::default(),
+        }
+    }
+}
+
+#[derive(PartialEq, Serialize)]
 pub struct ClockStore {
     pub groups: Vec<Group>,
     pub clocks: Vec<Clock>,
+    pub rates: Vec<Rate>,
+    pub absences: Vec<Absence>,
+    pub breaks: Vec<Break>,
 }
 
+#[derive(Debug)]
 pub struct ReadState {
     pub year: Option<i32>,
     pub date: Option<NaiveDate>,
     pub job: Option<String>,
-    pub tags: Vec<String>,
+    pub tags: Vec<Tag>,
     pub curr_in: Option<Clockin>,
+    pub last_out: Option<Moment>,
+    pub paused: Option<Moment>,
+}
+
+impl Default for ReadState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ReadState {
@@ -92,119 +308,304 @@ impl ReadState {
             job: None,
             tags: Vec::new(),
             curr_in: None,
+            last_out: None,
+            paused: None,
         }
     }
 }
 
+impl Default for ClockStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ClockStore {
     pub fn new() -> Self {
         ClockStore {
             groups: Vec::new(),
             clocks: Vec::new(),
+            rates: Vec::new(),
+            absences: Vec::new(),
+            breaks: Vec::new(),
         }
     }
 
-    pub fn read(&mut self, s: &str) -> Result<ReadState, ParseErr> {
+    /// Reading carries on after an error, so that every error in the file is returned.
+    pub fn read(&mut self, s: &str, fname: &str) -> Result<ReadState, ParseErrs> {
+        let file: Arc<str> = Arc::from(fname);
         let mut p = Parser::new(s);
         let mut rs = ReadState::new();
+        let mut errs = Vec::new();
 
         loop {
-            let action = p.next_action()?;
-            match action.ad {
-                ActionData::Group(name, members) => self.groups.push(Group { name, members }),
-                ActionData::ShortDate(dd, mm) => match &rs.year {
-                    Some(yr) => rs.date = Some(NaiveDate::from_ymd(*yr, mm, dd)),
-                    None => return Err(action.as_err(ErrType::YearNotSet)),
-                },
-                ActionData::LongDate(dd, mm, yy) => rs.date = Some(NaiveDate::from_ymd(yy, mm, dd)),
-                ActionData::SetJob(j) => rs.job = Some(j.to_string()),
-                ActionData::SetYear(yr) => rs.year = Some(yr),
-                ActionData::ClearTags => rs.tags.clear(),
-                ActionData::ClearTag(t) => rs.tags.retain(|i| i != t),
-                ActionData::Tag(t) => {
-                    if !rs.tags.iter().find(|i| *i == t).is_some() {
-                        rs.tags.push(t.to_string())
-                    }
+            match p
+                .next_action()
+                .and_then(|a| self.apply_action(a, &mut rs, &file))
+            {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => {
+                    p.recover(e.line);
+                    errs.push(e);
                 }
-                ActionData::Clockin(t) => {
-                    if let Some(last) = rs.curr_in.take() {
-                        //TODO add checks
-                        self.clocks.push(last.as_clock(t));
-                    }
-                    rs.curr_in = Some(Clockin {
-                        c_in: Moment::new(
-                            rs.date
-                                .clone()
-                                .take()
-                                .ok_or(action.as_err(ErrType::DateNotSet))?,
-                            t,
-                        ),
-                        job: rs
-                            .job
-                            .clone()
-                            .take()
-                            .ok_or(action.as_err(ErrType::JobNotSet))?,
-                        tags: rs.tags.clone(),
-                    })
+            }
+        }
+        match errs.is_empty() {
+            true => Ok(rs),
+            false => Err(ParseErrs {
+                file: fname.to_string(),
+                src: s.to_string(),
+                errs,
+            }),
+        }
+    }
+
+    fn apply_action(
+        &mut self,
+        action: Action,
+        rs: &mut ReadState,
+        file: &Arc<str>,
+    ) -> Result<bool, ParseErr> {
+        let loc = SrcLoc {
+            file: file.clone(),
+            line: action.line,
+            col: action.col,
+        };
+        match action.ad {
+            ActionData::Group(name, members) => self.groups.push(Group { name, members }),
+            //A rate applies from the date it is written under
+            ActionData::Rate(target, amount, currency) => self.rates.push(Rate {
+                target,
+                amount,
+                currency,
+                from: rs.date,
+            }),
+            ActionData::ShortDate(dd, mm) => match &rs.year {
+                Some(yr) => {
+                    rs.date = Some(
+                        NaiveDate::from_ymd_opt(*yr, mm, dd)
+                            .ok_or_else(|| action.as_err(ErrType::DateNotValid))?,
+                    )
+                }
+                None => return Err(action.as_err(ErrType::YearNotSet)),
+            },
+            ActionData::LongDate(dd, mm, yy) => {
+                rs.date = Some(
+                    NaiveDate::from_ymd_opt(yy, mm, dd)
+                        .ok_or_else(|| action.as_err(ErrType::DateNotValid))?,
+                )
+            }
+            ActionData::SetJob(j) => rs.job = Some(j.into_owned()),
+            ActionData::SetYear(yr) => rs.year = Some(yr),
+            ActionData::ClearTags => rs.tags.clear(),
+            ActionData::ClearTag(k) => rs.tags.retain(|i| i.key != k),
+            ActionData::Tag(k, v) => Tag::new(&k, v.as_deref()).set(&mut rs.tags),
+            ActionData::Clockin(t) => {
+                let d = rs.date.ok_or(action.as_err(ErrType::DateNotSet))?;
+                let m = Moment::rolled(d, t).ok_or_else(|| action.as_err(ErrType::NotATime))?;
+                if let Some(last) = rs.curr_in.take() {
+                    self.clocks.push(last.as_clock(m, loc.clone()));
                 }
-                ActionData::Clockout(t) => {
-                    match rs.curr_in.take() {
-                        //with checks
-                        Some(i) => self.clocks.push(i.as_clock(t)),
-                        None => return Err(action.as_err(ErrType::ClockinNotSet)),
+                rs.last_out = None;
+                //A break only ends on the day it started, otherwise it is left open
+                if let Some(start) = rs.paused.take().filter(|s| s.d == m.d) {
+                    if let Some(b) = self.breaks.iter_mut().rev().find(|b| b.start == start) {
+                        b.end = Some(m);
                     }
                 }
-                ActionData::End => return Ok(rs),
+                rs.curr_in = Some(Clockin {
+                    c_in: m,
+                    job: rs.job.clone().ok_or(action.as_err(ErrType::JobNotSet))?,
+                    tags: rs.tags.clone(),
+                    note: None,
+                    loc,
+                })
             }
+            ActionData::Clockout(t) => {
+                let d = rs.date.ok_or(action.as_err(ErrType::DateNotSet))?;
+                let m = Moment::rolled(d, t).ok_or_else(|| action.as_err(ErrType::NotATime))?;
+                match rs.curr_in.take() {
+                    Some(i) => self.clocks.push(i.as_clock(m, loc)),
+                    None => return Err(action.as_err(ErrType::ClockinNotSet)),
+                }
+                rs.last_out = Some(m);
+            }
+            ActionData::ClockoutOn(dd, mm, yy, t) => {
+                let yr = yy
+                    .or(rs.year)
+                    .ok_or_else(|| action.as_err(ErrType::YearNotSet))?;
+                let d = NaiveDate::from_ymd_opt(yr, mm, dd)
+                    .ok_or_else(|| action.as_err(ErrType::DateNotValid))?;
+                let m = Moment::rolled(d, t).ok_or_else(|| action.as_err(ErrType::NotATime))?;
+                match rs.curr_in.take() {
+                    Some(i) => self.clocks.push(i.as_clock(m, loc)),
+                    None => return Err(action.as_err(ErrType::ClockinNotSet)),
+                }
+                rs.last_out = Some(m);
+            }
+            ActionData::Break => {
+                let start = rs
+                    .last_out
+                    .take()
+                    .ok_or_else(|| action.as_err(ErrType::BreakWithoutClockout))?;
+                self.breaks.push(Break { start, end: None });
+                rs.paused = Some(start);
+            }
+            ActionData::Note(ref n) => match &mut rs.curr_in {
+                Some(Clockin {
+                    note: Some(prev), ..
+                }) => {
+                    prev.push_str("; ");
+                    prev.push_str(n);
+                }
+                Some(i) => i.note = Some(n.clone()),
+                None => return Err(action.as_err(ErrType::NoteWithoutClockin)),
+            },
+            ActionData::Absence(kind, half, ref note) => {
+                let date = rs.date.ok_or(action.as_err(ErrType::DateNotSet))?;
+                let a = Absence {
+                    date,
+                    kind,
+                    half,
+                    note: note.clone(),
+                    loc,
+                };
+                //Across all the files read, so history can not double up a day either
+                let halves: u32 = self
+                    .absences
+                    .iter()
+                    .filter(|b| b.date == date)
+                    .map(|b| b.halves())
+                    .sum();
+                if halves + a.halves() > 2 {
+                    return Err(action.as_err(ErrType::AbsentOverADay));
+                }
+                self.absences.push(a);
+            }
+            ActionData::Comment(_) => {}
+            ActionData::End => return Ok(true),
         }
+        Ok(false)
+    }
+
+    /// The breaks that follow one of the clocks, so filtering the clocks filters these too
+    pub fn selected_breaks(&self) -> Vec<&Break> {
+        let outs: std::collections::BTreeSet<Moment> =
+            self.clocks.iter().map(|c| c.c_out).collect();
+        self.breaks
+            .iter()
+            .filter(|b| outs.contains(&b.start))
+            .collect()
     }
 
-    pub fn check_collisions(&mut self) {
+    pub fn check_collisions(&mut self) -> Vec<Collision> {
         self.clocks.sort();
-        self.clocks.dedup();
-        //TODO find collisions
+        let mut res = Vec::new();
+        for (i, a) in self.clocks.iter().enumerate() {
+            let a_end = a.end_mins();
+            for b in &self.clocks[i + 1..] {
+                if b.start_mins() >= a_end {
+                    break;
+                }
+                let ctype = match (a == b, a.c_in == b.c_in || b.end_mins() <= a_end) {
+                    (true, _) => CollisionType::Duplicate,
+                    (false, true) => CollisionType::Overlap,
+                    (false, false) => CollisionType::RunsInto,
+                };
+                res.push(Collision {
+                    a: a.clone(),
+                    b: b.clone(),
+                    ctype,
+                });
+            }
+        }
+        res
     }
 
-    pub fn as_time_map(self, print: bool) -> Result<BTreeMap<String, STime>, ClockErr> {
+    /// The raw and rounded totals for each job clocked.
+    /// Subtotals for the levels of a job path are left to the report.
+    pub fn as_time_map(
+        &self,
+        print: bool,
+        rules: &[RoundRule],
+    ) -> Result<BTreeMap<String, Totals>, ClockErr> {
+        let mut inc_list = vec![Totals::default(); self.clocks.len()];
+        for p in rounding::parts(&self.clocks, &self.groups, rules)? {
+            inc_list[p.clock] += p.t;
+        }
         let mut mp = BTreeMap::new();
-        let mut tot_time = STime::new(0, 0);
+        let mut tot_time = Totals::default();
         let mut last_date = NaiveDate::from_ymd(1, 1, 1);
-        for c in self.clocks {
+        for (c, inc) in self.clocks.iter().zip(inc_list) {
             if c.c_in.d != last_date {
                 last_date = c.c_in.d;
                 if print {
                     println!("{}", last_date.format("%d/%m/%Y"));
                 }
             }
-            if c.c_in.t > c.c_out {
-                return Err(ClockErr {
-                    clock: c,
-                    etype: ClockErrType::OutBeforeIn,
-                });
-            }
-            let inc = c.c_out - c.c_in.t;
             tot_time += inc;
-            match mp.get_mut(&c.job) {
-                Some(tot) => {
-                    *tot += inc;
-                    if print {
-                        println!(
-                            "  {:<15}: {}-{} = {} => {}   {}",
-                            c.job, c.c_in.t, c.c_out, inc, *tot, tot_time
-                        );
-                    }
-                }
-                None => {
-                    if print {
-                        println!(
-                            "  {:<15}: {}-{} = {} => {}   {}",
-                            c.job, c.c_in.t, c.c_out, inc, inc, tot_time
-                        );
-                    }
-                    mp.insert(c.job.to_string(), c.c_out - c.c_in.t);
-                }
+            let tot = mp.entry(c.job.clone()).or_default();
+            *tot += inc;
+            if print {
+                println!(
+                    "  {:<15}: {} = {} => {}   {}   {}{}",
+                    c.job,
+                    c.times(),
+                    inc,
+                    *tot,
+                    tot_time,
+                    c.loc,
+                    c.note_str()
+                );
             }
         }
         Ok(mp)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    pub fn test_check_collisions() {
+        let mut store = ClockStore::new();
+        store
+            .read("1/11/2021\n  work,9:00 -12:00\n  play,11:00 -13:00\n", "a")
+            .unwrap();
+        store
+            .read(
+                "1/11/2021\n  work,9:00 -12:00\n  10:00 -11:00\n  14:00 -15:00\n",
+                "b",
+            )
+            .unwrap();
+        let res: Vec<_> = store
+            .check_collisions()
+            .into_iter()
+            .map(|c| (c.ctype, c.b.loc.file.to_string(), c.b.loc.line))
+            .collect();
+        assert!(res.contains(&(CollisionType::Duplicate, "b".to_string(), 2)));
+        assert!(res.contains(&(CollisionType::Overlap, "b".to_string(), 3)));
+        assert!(res.contains(&(CollisionType::RunsInto, "a".to_string(), 3)));
+        assert_eq!(store.clocks[1].loc.col, 8);
+        assert_eq!(store.clocks[0].lines(), "line 2");
+        assert_eq!(res.len(), 5);
+    }
+
+    #[test]
+    pub fn test_read_reports_every_error() {
+        let mut store = ClockStore::new();
+        let errs = store
+            .read(
+                "1/11/2021\n  work,9:x0\n  10:00 -11:60\n  12:00 -13:00\n  31/2/2021\n",
+                "a",
+            )
+            .unwrap_err();
+        let lines: Vec<usize> = errs.errs.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+        assert_eq!(store.clocks.len(), 2);
+        let s = errs.to_string();
+        assert!(s.starts_with("3 errors reading a"));
+        assert!(s.contains("2 |   work,9:x0\n  |          ^"));
+    }
+}
@@ -0,0 +1,392 @@
+use crate::err::ClockErr;
+use crate::filter::job_matches;
+use crate::moment::Duration;
+use crate::reader::{Break, Clock, Group};
+use crate::rounding::{self, RoundRule, Totals};
+use chrono::naive::NaiveDate;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dim {
+    Job,
+    Tag,
+    TagKey(String),
+    Group,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl FromStr for Dim {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if let Some(k) = s.strip_prefix("tag:") {
+            return Ok(Dim::TagKey(k.to_string()));
+        }
+        match s {
+            "job" => Ok(Dim::Job),
+            "tag" => Ok(Dim::Tag),
+            "group" => Ok(Dim::Group),
+            "day" => Ok(Dim::Day),
+            "week" => Ok(Dim::Week),
+            "month" => Ok(Dim::Month),
+            "year" => Ok(Dim::Year),
+            _ => Err(anyhow::anyhow!(
+                "Cannot group by '{}', expected job,tag,tag:<key>,group,day,week,month or year",
+                s
+            )),
+        }
+    }
+}
+
+pub fn dims_from_str(s: &str) -> anyhow::Result<Vec<Dim>> {
+    s.split(',').map(Dim::from_str).collect()
+}
+
+impl Dim {
+    /// A clock with several tags or groups counts towards each of them.
+    pub fn keys(&self, c: &Clock, d: NaiveDate, groups: &[Group]) -> Vec<String> {
+        let res: Vec<String> = match self {
+            Dim::Job => vec![c.job.clone()],
+            Dim::Tag => c.tags.iter().map(|t| t.to_string()).collect(),
+            Dim::TagKey(k) => c
+                .tags
+                .iter()
+                .filter(|t| t.key == *k)
+                .filter_map(|t| t.value.clone())
+                .collect(),
+            Dim::Group => groups
+                .iter()
+                .filter(|g| g.members.iter().any(|m| job_matches(m, &c.job)))
+                .map(|g| g.name.clone())
+                .collect(),
+            Dim::Day => vec![d.format("%Y-%m-%d").to_string()],
+            Dim::Week => vec![d.format("%G-W%V").to_string()],
+            Dim::Month => vec![d.format("%Y-%m").to_string()],
+            Dim::Year => vec![d.format("%Y").to_string()],
+        };
+        match res.is_empty() {
+            true => vec!["(none)".to_string()],
+            false => res,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Node {
+    pub total: Duration,
+    pub rounded: Duration,
+    pub children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn add(&mut self, c: &Clock, d: NaiveDate, groups: &[Group], dims: &[Dim], t: Totals) {
+        self.total += t.raw;
+        self.rounded += t.rounded;
+        if let Some((dim, rest)) = dims.split_first() {
+            for k in dim.keys(c, d, groups) {
+                let path: Vec<&str> = match dim {
+                    Dim::Job => k.split('/').collect(),
+                    _ => vec![&k],
+                };
+                self.add_path(&path, c, d, groups, rest, t);
+            }
+        }
+    }
+
+    fn add_path(
+        &mut self,
+        path: &[&str],
+        c: &Clock,
+        d: NaiveDate,
+        groups: &[Group],
+        dims: &[Dim],
+        t: Totals,
+    ) {
+        if let Some((p, more)) = path.split_first() {
+            let child = self.children.entry(p.to_string()).or_default();
+            match more.is_empty() {
+                true => child.add(c, d, groups, dims, t),
+                false => {
+                    child.total += t.raw;
+                    child.rounded += t.rounded;
+                    child.add_path(more, c, d, groups, dims, t);
+                }
+            }
+        }
+    }
+
+    fn add_totals(&mut self, path: &str, t: Totals) {
+        self.total += t.raw;
+        self.rounded += t.rounded;
+        let (p, rest) = match path.split_once('/') {
+            Some((p, r)) => (p, Some(r)),
+            None => (path, None),
+        };
+        let child = self.children.entry(p.to_string()).or_default();
+        match rest {
+            Some(r) => child.add_totals(r, t),
+            None => {
+                child.total += t.raw;
+                child.rounded += t.rounded;
+            }
+        }
+    }
+}
+
+/// Clocks that run past midnight count towards each day they cover.
+pub fn group_by(
+    clocks: &[Clock],
+    groups: &[Group],
+    dims: &[Dim],
+    rules: &[RoundRule],
+) -> Result<Node, ClockErr> {
+    let mut root = Node::default();
+    for p in rounding::parts(clocks, groups, rules)? {
+        root.add(&clocks[p.clock], p.date, groups, dims, p.t);
+    }
+    Ok(root)
+}
+
+fn tree_width(n: &Node, depth: usize) -> usize {
+    n.children
+        .iter()
+        .map(|(k, v)| (depth * 2 + k.chars().count()).max(tree_width(v, depth + 1)))
+        .max()
+        .unwrap_or(0)
+}
+
+fn node_line(res: &mut String, name: &str, n: &Node, width: usize, total: Duration, rounded: bool) {
+    write!(res, "{:<width$}  {:>7}", name, n.total, width = width).ok();
+    if rounded {
+        write!(res, "  {:>7}", n.rounded).ok();
+    }
+    writeln!(
+        res,
+        "  {:>7.2}  {:>5.1}%",
+        hours(n.total),
+        percent(n.total, total)
+    )
+    .ok();
+}
+
+fn write_tree(
+    res: &mut String,
+    n: &Node,
+    depth: usize,
+    width: usize,
+    total: Duration,
+    rounded: bool,
+) {
+    for (k, v) in &n.children {
+        let name = format!("{}{}", "  ".repeat(depth), k);
+        node_line(res, &name, v, width, total, rounded);
+        write_tree(res, v, depth + 1, width, total, rounded);
+    }
+}
+
+pub fn render_tree(root: &Node, rounded: bool) -> String {
+    render_nodes(root, "Group", rounded)
+}
+
+fn render_nodes(root: &Node, heading: &str, rounded: bool) -> String {
+    let width = tree_width(root, 0).max(5);
+    let mut res = String::new();
+    write!(res, "{:<width$}  {:>7}", heading, "Time", width = width).ok();
+    if rounded {
+        write!(res, "  {:>7}", "Rounded").ok();
+    }
+    writeln!(res, "  {:>7}  {:>6}", "Hours", "Share").ok();
+    write_tree(&mut res, root, 0, width, root.total, rounded);
+    let line = width + if rounded { 37 } else { 28 };
+    writeln!(res, "{}", "-".repeat(line)).ok();
+    node_line(&mut res, "Total", root, width, root.total, rounded);
+    res
+}
+
+pub fn percent(part: Duration, total: Duration) -> f64 {
+    match total.mins() {
+        0 => 0.,
+        t => part.mins() as f64 * 100. / t as f64,
+    }
+}
+
+pub fn hours(t: Duration) -> f64 {
+    t.mins() as f64 / 60.
+}
+
+pub fn render_summary(mp: &BTreeMap<String, Totals>, rounded: bool) -> String {
+    let mut root = Node::default();
+    for (k, v) in mp {
+        root.add_totals(k, *v);
+    }
+    render_nodes(&root, "Job", rounded)
+}
+
+pub fn render_breaks(breaks: &[&Break]) -> String {
+    let total: Duration = breaks.iter().map(|b| b.duration()).sum();
+    match breaks.len() {
+        0 => String::new(),
+        1 => format!("Breaks: {} in 1 break\n", total),
+        n => format!("Breaks: {} in {} breaks\n", total, n),
+    }
+}
+
+/// The rounded time of the clocks, which should not include the credited absences
+pub fn budget_used(
+    clocks: &[Clock],
+    groups: &[Group],
+    rules: &[RoundRule],
+) -> Result<Duration, ClockErr> {
+    Ok(rounding::parts(clocks, groups, rules)?
+        .into_iter()
+        .map(|p| p.t.rounded)
+        .sum())
+}
+
+pub fn render_budget(budget: Duration, used: Duration) -> String {
+    let left = budget - used;
+    match left.is_negative() {
+        true => format!("Budget: {}, used {}, over by {}\n", budget, used, -left),
+        false => format!("Budget: {}, used {}, left {}\n", budget, used, left),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    pub fn test_summary_totals() {
+        let mut mp = BTreeMap::new();
+        mp.insert("work".to_string(), Totals::new(Duration::new(3, 0)));
+        mp.insert("play".to_string(), Totals::new(Duration::new(1, 0)));
+        let s = render_summary(&mp, false);
+        assert!(s.contains("work     03:00     3.00   75.0%"));
+        assert!(s.contains("Total    04:00     4.00  100.0%"));
+        assert_eq!(
+            render_budget("1.5h".parse().unwrap(), Duration::new(2, 0)),
+            "Budget: 01:30, used 02:00, over by 00:30\n"
+        );
+    }
+
+    #[test]
+    pub fn test_job_paths_roll_up() {
+        let mut store = crate::reader::ClockStore::new();
+        store
+            .read("1/11/2021\n  acme/web,9:00 -11:00\n  acme/app/login,13:00 -14:00\n  home,15:00 -15:30\n", "test")
+            .unwrap();
+        let mp = store.as_time_map(false, &[]).unwrap();
+        //Only the jobs clocked, the subtotals are left to the report
+        assert!(!mp.contains_key("acme"));
+        assert_eq!(mp["acme/app/login"].raw, Duration::new(1, 0));
+        let s = render_summary(&mp, false);
+        assert!(s.contains("acme         03:00"));
+        assert!(s.contains("    login    01:00"));
+        assert!(s.contains("Total        03:30"));
+        //The budget counts each clock once, not again for each level of its path
+        let used = budget_used(&store.clocks, &store.groups, &[]).unwrap();
+        assert_eq!(
+            render_budget("10h".parse().unwrap(), used),
+            "Budget: 10:00, used 03:30, left 06:30\n"
+        );
+
+        let root = group_by(&store.clocks, &store.groups, &[Dim::Job, Dim::Day], &[]).unwrap();
+        let app = &root.children["acme"].children["app"];
+        assert_eq!(app.total, Duration::new(1, 0));
+        assert!(app.children["login"].children.contains_key("2021-11-01"));
+    }
+
+    #[test]
+    pub fn test_budget_leaves_out_absences() {
+        let mut store = crate::reader::ClockStore::new();
+        store
+            .read("1/11/2021\n  !vacation half\n  work,9:00 -10:00\n", "test")
+            .unwrap();
+        let h = Duration::new(8, 0);
+        let z = Duration::default();
+        let targets = vec![crate::balance::Target {
+            from: None,
+            days: [h, z, z, z, z, z, z],
+        }];
+        let mut clocks = store.clocks.clone();
+        clocks.extend(crate::absence::credited_clocks(&store.absences, &targets));
+        let root = group_by(&clocks, &store.groups, &[Dim::Job], &[]).unwrap();
+        assert_eq!(root.total, Duration::new(5, 0));
+        let used = budget_used(&store.clocks, &store.groups, &[]).unwrap();
+        assert_eq!(
+            render_budget("2h".parse().unwrap(), used),
+            "Budget: 02:00, used 01:00, left 01:00\n"
+        );
+    }
+
+    #[test]
+    pub fn test_group_by_month_then_job() {
+        let mut store = crate::reader::ClockStore::new();
+        store
+            .read("$fun[play]\n1/11/2021\n  work,9:00 -12:00\n  play,13:00 -14:00\n1/12/2021\n  work,9:00 -10:00\n", "test")
+            .unwrap();
+        let dims = dims_from_str("month,job").unwrap();
+        let root = group_by(&store.clocks, &store.groups, &dims, &[]).unwrap();
+        assert_eq!(root.total, Duration::new(5, 0));
+        let nov = &root.children["2021-11"];
+        assert_eq!(nov.total, Duration::new(4, 0));
+        assert_eq!(nov.children["play"].total, Duration::new(1, 0));
+
+        let root = group_by(&store.clocks, &store.groups, &[Dim::Group], &[]).unwrap();
+        assert_eq!(root.children["(none)"].total, Duration::new(4, 0));
+        assert_eq!(root.children["fun"].total, Duration::new(1, 0));
+    }
+
+    #[test]
+    pub fn test_group_by_tag_key() {
+        let mut store = crate::reader::ClockStore::new();
+        store
+            .read("1/11/2021\n  work,_ticket=ABC-1,_urgent 9:00 -10:00\n  _ticket=ABC-2 10:00 -12:00\n  __ 13:00 -14:00\n", "test")
+            .unwrap();
+        assert_eq!(store.clocks[1].tags.len(), 2);
+        let dims = dims_from_str("tag:ticket").unwrap();
+        let root = group_by(&store.clocks, &store.groups, &dims, &[]).unwrap();
+        assert_eq!(root.children["ABC-1"].total, Duration::new(1, 0));
+        assert_eq!(root.children["ABC-2"].total, Duration::new(2, 0));
+        assert_eq!(root.children["(none)"].total, Duration::new(1, 0));
+    }
+
+    #[test]
+    pub fn test_group_by_day_splits_night_shift() {
+        let mut store = crate::reader::ClockStore::new();
+        store
+            .read("1/11/2021\n  work,22:00 -2/11/2021 6:30\n", "test")
+            .unwrap();
+        let root = group_by(&store.clocks, &store.groups, &[Dim::Day], &[]).unwrap();
+        assert_eq!(root.total, Duration::new(8, 30));
+        assert_eq!(root.children["2021-11-01"].total, Duration::new(2, 0));
+        assert_eq!(root.children["2021-11-02"].total, Duration::new(6, 30));
+    }
+
+    #[test]
+    pub fn test_rounded_column() {
+        let mut store = crate::reader::ClockStore::new();
+        store
+            .read(
+                "1/11/2021\n  work,9:00 -10:08\n  play,11:00 -11:20\n",
+                "test",
+            )
+            .unwrap();
+        let rules = vec![RoundRule {
+            target: None,
+            rounding: "15:up".parse().unwrap(),
+        }];
+        let mp = store.as_time_map(false, &rules).unwrap();
+        assert_eq!(mp["work"].rounded, Duration::new(1, 15));
+        let s = render_summary(&mp, true);
+        assert!(s.contains("Job       Time  Rounded    Hours   Share"));
+        assert!(s.contains("work     01:08    01:15     1.13   77.3%"));
+        assert!(s.contains("Total    01:28    01:45     1.47  100.0%"));
+        let root = group_by(&store.clocks, &store.groups, &[Dim::Day], &rules).unwrap();
+        assert_eq!(root.children["2021-11-01"].rounded, Duration::new(1, 45));
+    }
+}
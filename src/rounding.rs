@@ -0,0 +1,229 @@
+use crate::billing::RateTarget;
+use crate::err::ClockErr;
+use crate::moment::{Duration, MAX_MINS};
+use crate::reader::{Clock, Group};
+use chrono::naive::NaiveDate;
+use derive_more::*;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundDir {
+    Up,
+    Down,
+    Nearest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundPer {
+    Clock,
+    Day,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rounding {
+    pub step: i64,
+    pub dir: RoundDir,
+    pub per: RoundPer,
+}
+
+impl Rounding {
+    pub fn round(&self, d: Duration) -> Duration {
+        let (m, s) = (d.mins(), self.step);
+        if s <= 1 {
+            return d;
+        }
+        let n = match self.dir {
+            RoundDir::Up => (m + s - 1).div_euclid(s),
+            RoundDir::Down => m.div_euclid(s),
+            //Halves go up
+            RoundDir::Nearest => (m + s / 2).div_euclid(s),
+        };
+        Duration::new(0, n * s)
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut parts = s.split(':').map(str::trim);
+        let step: i64 = match parts.next().map(str::parse) {
+            Some(Ok(n)) if n > 0 && n <= MAX_MINS => n,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Rounding '{}' should start with a number of minutes, as in 15:up:day",
+                    s
+                ))
+            }
+        };
+        let mut res = Rounding {
+            step,
+            dir: RoundDir::Nearest,
+            per: RoundPer::Clock,
+        };
+        for p in parts {
+            match p {
+                "up" => res.dir = RoundDir::Up,
+                "down" => res.dir = RoundDir::Down,
+                "nearest" => res.dir = RoundDir::Nearest,
+                "clock" => res.per = RoundPer::Clock,
+                "day" => res.per = RoundPer::Day,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Unknown rounding '{}', expected up,down,nearest,clock or day",
+                        p
+                    ))
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundRule {
+    pub target: Option<RateTarget>,
+    pub rounding: Rounding,
+}
+
+/// The closest fit wins, then the last defined.
+pub fn rule_for<'a>(rules: &'a [RoundRule], job: &str, groups: &[Group]) -> Option<&'a Rounding> {
+    rules
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| {
+            let fit = match &r.target {
+                Some(t) => t.fit(job, groups)?,
+                None => 0,
+            };
+            Some(((fit, i), &r.rounding))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, r)| r)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Add, AddAssign)]
+pub struct Totals {
+    pub raw: Duration,
+    pub rounded: Duration,
+}
+
+impl Totals {
+    pub fn new(raw: Duration) -> Self {
+        Totals { raw, rounded: raw }
+    }
+}
+
+impl Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.raw == self.rounded {
+            true => write!(f, "{}", self.raw),
+            false => write!(f, "{}~{}", self.raw, self.rounded),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Part {
+    pub clock: usize,
+    pub date: NaiveDate,
+    pub t: Totals,
+}
+
+/// The difference rounding makes is put on the last part it covers,
+/// so a day's parts still add up to the rounded total for that day.
+pub fn parts(
+    clocks: &[Clock],
+    groups: &[Group],
+    rules: &[RoundRule],
+) -> Result<Vec<Part>, ClockErr> {
+    let mut res = Vec::new();
+    //(job, date) to the index of its last part, the raw time so far and the rounding
+    let mut days: BTreeMap<(&str, NaiveDate), (usize, Duration, &Rounding)> = BTreeMap::new();
+    for (i, c) in clocks.iter().enumerate() {
+        let rule = rule_for(rules, &c.job, groups);
+        for (date, t) in c.day_parts()? {
+            res.push(Part {
+                clock: i,
+                date,
+                t: Totals::new(t),
+            });
+            if let Some(r) = rule.filter(|r| r.per == RoundPer::Day) {
+                let e = days
+                    .entry((&c.job, date))
+                    .or_insert((0, Duration::default(), r));
+                e.0 = res.len() - 1;
+                e.1 += t;
+            }
+        }
+        if let Some(r) = rule.filter(|r| r.per == RoundPer::Clock) {
+            let raw = c.duration()?;
+            if let Some(last) = res.last_mut() {
+                last.t.rounded += r.round(raw) - raw;
+            }
+        }
+    }
+    for (last, raw, r) in days.into_values() {
+        res[last].t.rounded += r.round(raw) - raw;
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::ClockStore;
+    #[test]
+    pub fn test_rounding_steps() {
+        let r: Rounding = "15".parse().unwrap();
+        assert_eq!(r.round(Duration::new(0, 52)), Duration::new(0, 45));
+        assert_eq!(r.round(Duration::new(0, 53)), Duration::new(1, 0));
+        let r: Rounding = "6:up".parse().unwrap();
+        assert_eq!(r.round(Duration::new(0, 13)), Duration::new(0, 18));
+        assert_eq!(r.round(Duration::new(0, 12)), Duration::new(0, 12));
+        let r: Rounding = "30:down:day".parse().unwrap();
+        assert_eq!(r.per, RoundPer::Day);
+        assert_eq!(r.round(Duration::new(0, 59)), Duration::new(0, 30));
+        assert!("0".parse::<Rounding>().is_err());
+        assert!("15:sideways".parse::<Rounding>().is_err());
+    }
+
+    #[test]
+    pub fn test_parts_per_clock_and_per_day() {
+        let mut store = ClockStore::new();
+        store
+            .read(
+                "$fun[play]\n1/11/2021\n  acme,9:00 -9:10\n  9:20 -9:30\n  play,10:00 -10:05\n  home,11:00 -11:07\n",
+                "test",
+            )
+            .unwrap();
+        let rules = vec![
+            RoundRule {
+                target: None,
+                rounding: "15:up".parse().unwrap(),
+            },
+            RoundRule {
+                target: Some(RateTarget::Job("acme".to_string())),
+                rounding: "15:up:day".parse().unwrap(),
+            },
+            RoundRule {
+                target: Some(RateTarget::Group("fun".to_string())),
+                rounding: "15:down".parse().unwrap(),
+            },
+        ];
+        let p = parts(&store.clocks, &store.groups, &rules).unwrap();
+        let rounded: Vec<Duration> = p.iter().map(|p| p.t.rounded).collect();
+        //acme 0:20 in the day goes up to 0:30, on its last clock
+        assert_eq!(
+            rounded,
+            vec![
+                Duration::new(0, 10),
+                Duration::new(0, 20),
+                Duration::new(0, 0),
+                Duration::new(0, 15)
+            ]
+        );
+        assert_eq!(p[3].t.raw, Duration::new(0, 7));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::err::*;
 pub type TokenRes<'a> = Result<Token<'a>, ParseErr>;
 
+use std::borrow::Cow;
 use std::str::FromStr;
 #[derive(Debug)]
 pub struct Token<'a> {
@@ -22,14 +23,39 @@ impl<'a> Token<'a> {
     pub fn num_val(&self) -> Result<u32, ParseErr> {
         u32::from_str(self.s).map_err(|_| self.as_err(ErrType::NotANumber))
     }
+
+    pub fn ident_val(&self) -> Cow<'a, str> {
+        match self.s.starts_with('\'') {
+            true => Cow::Owned(self.unquote()),
+            false => Cow::Borrowed(self.s),
+        }
+    }
+
+    pub fn unquote(&self) -> String {
+        let mut res = String::new();
+        let mut it = self.s[1..self.s.len() - 1].chars();
+        while let Some(c) = it.next() {
+            match c {
+                '\\' => res.extend(it.next()),
+                c => res.push(c),
+            }
+        }
+        res
+    }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum TokenType {
     Sep,
+    Comment,
     Time,
     Ident,
+    Note,
     Dollar,
+    At,
+    Bang,
+    Tilde,
+    Dot,
     Number,
     Colon,
     Slash,
@@ -42,6 +68,33 @@ pub enum TokenType {
     EOF,
 }
 
+impl TokenType {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TokenType::Sep => "',' or a new line",
+            TokenType::Comment => "a comment",
+            TokenType::Time => "a time",
+            TokenType::Ident => "a name",
+            TokenType::Note => "a quoted note",
+            TokenType::Dollar => "'$'",
+            TokenType::At => "'@'",
+            TokenType::Bang => "'!'",
+            TokenType::Tilde => "'~'",
+            TokenType::Dot => "'.'",
+            TokenType::Number => "a number",
+            TokenType::Colon => "':'",
+            TokenType::Slash => "'/'",
+            TokenType::Minus => "'-'",
+            TokenType::Equals => "'='",
+            TokenType::SquareOpen => "'['",
+            TokenType::SquareClose => "']'",
+            TokenType::Tag => "'_'",
+            TokenType::ClearTag => "'__'",
+            TokenType::EOF => "the end of the file",
+        }
+    }
+}
+
 pub struct Tokenizer<'a> {
     s: &'a str,
     t_start: usize,
@@ -52,20 +105,23 @@ pub struct Tokenizer<'a> {
 impl<'a> Tokenizer<'a> {
     pub fn new(s: &'a str) -> Self {
         Tokenizer {
-            s: s,
+            s,
             t_start: 0,
             line: 1,
             col: 0,
         }
     }
 
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
     pub fn chars(&mut self) -> std::str::CharIndices<'a> {
         self.s[self.t_start..].char_indices()
     }
 
     fn whitespace(&mut self) {
-        let mut tmp = self.s[self.t_start..].char_indices();
-        while let Some((i, c)) = tmp.next() {
+        for (i, c) in self.s[self.t_start..].char_indices() {
             self.col += 1;
             match c {
                 ' ' | '\t' | '\r' => {}
@@ -82,46 +138,58 @@ impl<'a> Tokenizer<'a> {
         self.whitespace();
         match self.s[self.t_start..].chars().next() {
             Some('\n') => {
+                let tk = self.make_token(1, TokenType::Sep);
                 self.line += 1;
                 self.col = 0;
-                Ok(self.make_token(1, TokenType::Sep))
+                Ok(tk)
             }
 
             Some('#') => {
-                let mut tmp = self.s[self.t_start..].char_indices();
-                while let Some((i, c)) = tmp.next() {
-                    if c == '\n' {
-                        self.col = 0;
-                        self.line += 1;
-                        return Ok(self.make_token(i + 1, TokenType::Sep));
-                    }
-                }
-                Ok(self.make_token(self.s[self.t_start..].len(), TokenType::EOF))
+                let len = match self.s[self.t_start..].find('\n') {
+                    Some(n) => n,
+                    None => self.s.len() - self.t_start,
+                };
+                Ok(self.make_token(len, TokenType::Comment))
             }
             Some(',') => Ok(self.make_token(1, TokenType::Sep)),
             Some('_') => self.make_tag(),
+            Some('"') => self.quoted('"', TokenType::Note),
+            Some('\'') => self.quoted('\'', TokenType::Ident),
 
             Some('$') => Ok(self.make_token(1, TokenType::Dollar)),
+            Some('@') => Ok(self.make_token(1, TokenType::At)),
+            Some('!') => Ok(self.make_token(1, TokenType::Bang)),
+            Some('~') => Ok(self.make_token(1, TokenType::Tilde)),
+            Some('.') => Ok(self.make_token(1, TokenType::Dot)),
             Some(':') => Ok(self.make_token(1, TokenType::Colon)),
             Some('/') => Ok(self.make_token(1, TokenType::Slash)),
             Some('[') => Ok(self.make_token(1, TokenType::SquareOpen)),
             Some(']') => Ok(self.make_token(1, TokenType::SquareClose)),
             Some('-') => Ok(self.make_token(1, TokenType::Minus)),
             Some('=') => Ok(self.make_token(1, TokenType::Equals)),
-            Some(c) if c >= '0' && c <= '9' => self.number(),
+            Some(c) if c.is_ascii_digit() => self.number(),
             Some(c) if c.is_alphabetic() => Ok(self.ident()),
-            Some(_) => self.make_err(ErrType::NoToken),
+            Some(c) => {
+                //Step over the bad char so the parser can carry on
+                let e = self.make_err(ErrType::NoToken);
+                self.t_start += c.len_utf8();
+                e
+            }
             None => Ok(self.make_token(0, TokenType::EOF)),
         }
     }
 
+    /// len is in bytes, self.col should be on the first char of the token,
+    /// and is left on the last one.
     pub fn make_token(&mut self, len: usize, tt: TokenType) -> Token<'a> {
+        let s = &self.s[self.t_start..self.t_start + len];
         let res = Token {
-            s: &self.s[self.t_start..self.t_start + len],
+            s,
             line: self.line,
-            col: self.col + 1 - len,
+            col: self.col,
             tt,
         };
+        self.col += s.chars().count().saturating_sub(1);
         self.t_start += len;
         res
     }
@@ -135,27 +203,44 @@ impl<'a> Tokenizer<'a> {
     }
 
     pub fn number(&mut self) -> TokenRes<'a> {
-        let mut tmp = self.chars();
-        while let Some((i, c)) = tmp.next() {
-            if c < '0' || c > '9' {
+        for (i, c) in self.chars() {
+            if !c.is_ascii_digit() {
                 return Ok(self.make_token(i, TokenType::Number));
             }
-            self.col += 1;
         }
         Ok(self.make_token(self.s.len() - self.t_start, TokenType::Number))
     }
 
     pub fn ident(&mut self) -> Token<'a> {
-        let mut tmp = self.chars();
-        while let Some((i, c)) = tmp.next() {
-            if !c.is_alphabetic() && c != '_' && (c < '0' || c > '9') {
+        for (i, c) in self.chars() {
+            if !c.is_alphabetic() && c != '_' && !c.is_ascii_digit() {
                 return self.make_token(i, TokenType::Ident);
             }
-            self.col += 1;
         }
         self.make_token(self.s.len() - self.t_start, TokenType::Ident)
     }
 
+    pub fn quoted(&mut self, q: char, tt: TokenType) -> TokenRes<'a> {
+        let mut escaped = false;
+        for (i, c) in self.chars().skip(1) {
+            match c {
+                '\n' => break,
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                c if c == q => return Ok(self.make_token(i + c.len_utf8(), tt)),
+                _ => {}
+            }
+        }
+        //Step over the rest of the line so the parser can carry on
+        let e = self.make_err(ErrType::QuoteNotClosed);
+        let len = match self.s[self.t_start..].find('\n') {
+            Some(n) => n,
+            None => self.s.len() - self.t_start,
+        };
+        self.make_token(len, tt);
+        e
+    }
+
     pub fn make_tag(&mut self) -> TokenRes<'a> {
         match self.s[self.t_start + 1..].chars().next() {
             Some('_') => Ok(self.make_token(2, TokenType::ClearTag)),
@@ -0,0 +1,420 @@
+use crate::absence::{Absence, AbsenceKind};
+use crate::billing::{Money, Rate, RateTarget};
+use crate::moment::{Moment, STime};
+use crate::reader::{ClockStore, Clockin, Tag};
+use chrono::naive::NaiveDate;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutFormat {
+    Tock,
+    Json,
+    Yaml,
+}
+
+impl FromStr for OutFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "tock" => Ok(OutFormat::Tock),
+            "json" => Ok(OutFormat::Json),
+            "yaml" => Ok(OutFormat::Yaml),
+            _ => Err(anyhow::anyhow!(
+                "Unknown format '{}', expected one of tock,json,yaml",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OpenStore<'a> {
+    #[serde(flatten)]
+    store: &'a ClockStore,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open: Option<&'a Clockin>,
+}
+
+pub fn write_store(
+    store: &ClockStore,
+    open: Option<&Clockin>,
+    f: OutFormat,
+) -> anyhow::Result<String> {
+    let os = OpenStore { store, open };
+    match f {
+        OutFormat::Tock => {
+            let mut s = String::new();
+            write_tock(store, open, &mut s)?;
+            Ok(s)
+        }
+        OutFormat::Json => Ok(serde_json::to_string_pretty(&os)? + "\n"),
+        OutFormat::Yaml => Ok(serde_yaml::to_string(&os)?),
+    }
+}
+
+/// The list of tag tokens needed to get from one tag list to the next,
+/// keeping the order the tags will be read back in.
+pub fn tag_changes(prev: &[Tag], next: &[Tag]) -> Vec<String> {
+    //Setting a tag again only changes its value, so keys in the same order can be kept
+    let same_keys = next.len() >= prev.len() && prev.iter().zip(next).all(|(a, b)| a.key == b.key);
+    let mut res = Vec::new();
+    if !same_keys {
+        res.push("__".to_string());
+    }
+    for (i, t) in next.iter().enumerate() {
+        if !same_keys || prev.get(i) != Some(t) {
+            res.push(format!("_{}", tag_str(t)));
+        }
+    }
+    res
+}
+
+#[derive(Default)]
+pub struct Line {
+    pub lead: String,
+    pub out: Option<String>,
+    pub comment: Option<String>,
+    pub indent: bool,
+    pub brk: bool,
+}
+
+impl Line {
+    pub fn clock(lead: String, out: Option<String>) -> Self {
+        Line {
+            lead,
+            out,
+            comment: None,
+            indent: true,
+            brk: false,
+        }
+    }
+}
+
+pub fn quote(s: &str, q: char) -> String {
+    let mut res = String::new();
+    res.push(q);
+    for c in s.chars() {
+        match c {
+            '\n' => res.push(' '),
+            '\\' => res.push_str("\\\\"),
+            c if c == q => {
+                res.push('\\');
+                res.push(c);
+            }
+            c => res.push(c),
+        }
+    }
+    res.push(q);
+    res
+}
+
+pub fn quote_ident(s: &str) -> Cow<'_, str> {
+    let mut cs = s.chars();
+    let plain = match cs.next() {
+        Some(c) if c.is_alphabetic() => {
+            cs.all(|c| c.is_alphabetic() || c.is_ascii_digit() || c == '_')
+        }
+        _ => false,
+    };
+    match plain {
+        true => Cow::Borrowed(s),
+        false => Cow::Owned(quote(s, '\'')),
+    }
+}
+
+pub fn quote_job(s: &str) -> String {
+    let parts: Vec<_> = s.split('/').map(quote_ident).collect();
+    parts.join("/")
+}
+
+pub fn tag_str(t: &Tag) -> String {
+    let v = match &t.value {
+        Some(v) => v,
+        None => return quote_ident(&t.key).into_owned(),
+    };
+    let plain = v.starts_with(|c: char| c.is_alphanumeric())
+        && v.chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    match plain {
+        true => format!("{}={}", quote_ident(&t.key), v),
+        false => format!("{}={}", quote_ident(&t.key), quote(v, '\'')),
+    }
+}
+
+pub fn rate_str(target: &RateTarget, amount: Money, currency: Option<&str>) -> String {
+    let mut res = match target {
+        RateTarget::Job(j) => format!("@{} {}", quote_job(j), amount),
+        RateTarget::Group(g) => format!("@${} {}", quote_ident(g), amount),
+    };
+    if let Some(c) = currency {
+        res.push(' ');
+        res.push_str(&quote_ident(c));
+    }
+    res
+}
+
+pub fn absence_str(kind: AbsenceKind, half: bool, note: Option<&str>) -> String {
+    let mut res = format!("!{}", kind);
+    if half {
+        res.push_str(" half");
+    }
+    if let Some(n) = note {
+        res.push(' ');
+        res.push_str(&quote(n, '"'));
+    }
+    res
+}
+
+pub fn group_str(name: &str, members: &[String]) -> String {
+    let members: Vec<_> = members.iter().map(|m| quote_job(m)).collect();
+    format!("${}[{}]", quote_ident(name), members.join(","))
+}
+
+pub fn out_str(day: NaiveDate, out: Moment) -> String {
+    match out.d == day {
+        true => out.t.to_string(),
+        false => format!("{} {}", out.d.format("%d/%m/%Y"), out.t),
+    }
+}
+
+pub fn clock_lead(job: Option<&str>, tag_changes: Vec<String>, t: Option<STime>) -> String {
+    let mut parts: Vec<String> = job.map(quote_job).into_iter().collect();
+    parts.extend(tag_changes);
+    parts.extend(t.map(|t| t.to_string()));
+    parts.join(",")
+}
+
+pub struct Block {
+    pub date: Option<NaiveDate>,
+    pub comment: Option<String>,
+    pub lines: Vec<Line>,
+}
+
+impl Block {
+    pub fn new(date: Option<NaiveDate>) -> Self {
+        Block {
+            date,
+            comment: None,
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.date.is_none() && self.lines.is_empty()
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> fmt::Result {
+        if let Some(d) = self.date {
+            match &self.comment {
+                Some(c) => writeln!(w, "{} {}", d.format("%d/%m/%Y"), c)?,
+                None => writeln!(w, "{}", d.format("%d/%m/%Y"))?,
+            }
+        }
+        let width = self
+            .lines
+            .iter()
+            .filter(|l| l.out.is_some())
+            .map(|l| l.lead.chars().count())
+            .max()
+            .unwrap_or(0);
+        for l in &self.lines {
+            let mut s = String::new();
+            if l.indent {
+                s.push_str("  ");
+            }
+            match &l.out {
+                Some(o) => write!(s, "{:<width$} -{}", l.lead, o, width = width)?,
+                None => s.push_str(&l.lead),
+            }
+            if l.brk {
+                s.push_str(match s.trim().is_empty() {
+                    true => "~",
+                    false => " ~",
+                });
+            }
+            if let Some(c) = &l.comment {
+                if !l.lead.is_empty() || l.out.is_some() {
+                    s.push(' ');
+                }
+                s.push_str(c);
+            }
+            writeln!(w, "{}", s)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the store in the tock format, so that reading the result
+/// gives back the same groups and clocks.
+pub fn write_tock<W: Write>(store: &ClockStore, open: Option<&Clockin>, w: &mut W) -> fmt::Result {
+    for g in &store.groups {
+        writeln!(w, "{}", group_str(&g.name, &g.members))?;
+    }
+
+    //Rates are written under the date they apply from, and absences under their day
+    let mut rates: Vec<&Rate> = store.rates.iter().collect();
+    rates.sort_by_key(|r| r.from);
+    let mut absences: Vec<&Absence> = store.absences.iter().collect();
+    absences.sort_by_key(|a| a.date);
+    let mut dated = Dated {
+        rates: rates.into_iter().peekable(),
+        absences: absences.into_iter().peekable(),
+    };
+
+    let breaks: BTreeSet<Moment> = store.breaks.iter().map(|b| b.start).collect();
+    let mut job: Option<&str> = None;
+    let mut tags: &[Tag] = &[];
+    let mut block = Block::new(None);
+
+    //The open clockin goes last, with no clockout
+    let clocks = store
+        .clocks
+        .iter()
+        .map(|c| (c.c_in, &c.job, &c.tags, &c.note, Some(c.c_out)));
+    let open = open.map(|ci| (ci.c_in, &ci.job, &ci.tags, &ci.note, None));
+    for (c_in, c_job, c_tags, note, c_out) in clocks.chain(open) {
+        if block.date != Some(c_in.d) {
+            block.write(w)?;
+            block = Block::new(None);
+            while let Some((d, l)) = dated.next_until(Some(c_in.d)) {
+                put_dated(w, &mut block, d, l)?;
+            }
+            if block.date != Some(c_in.d) {
+                block.write(w)?;
+                block = Block::new(Some(c_in.d));
+            }
+        }
+        let new_job = match job == Some(c_job) {
+            true => None,
+            false => Some(c_job.as_str()),
+        };
+        job = Some(c_job);
+        let mut lead = clock_lead(new_job, tag_changes(tags, c_tags), Some(c_in.t));
+        if let Some(n) = note {
+            write!(lead, " {}", quote(n, '"'))?;
+        }
+        tags = c_tags;
+        let mut line = Line::clock(lead, c_out.map(|o| out_str(c_in.d, o)));
+        line.brk = c_out.is_some_and(|o| breaks.contains(&o));
+        block.lines.push(line);
+    }
+    while let Some((d, l)) = dated.next_until(None) {
+        put_dated(w, &mut block, d, l)?;
+    }
+    block.write(w)
+}
+
+struct Dated<'a, R: Iterator<Item = &'a Rate>, A: Iterator<Item = &'a Absence>> {
+    rates: std::iter::Peekable<R>,
+    absences: std::iter::Peekable<A>,
+}
+
+impl<'a, R: Iterator<Item = &'a Rate>, A: Iterator<Item = &'a Absence>> Dated<'a, R, A> {
+    fn next_until(&mut self, until: Option<NaiveDate>) -> Option<(Option<NaiveDate>, Line)> {
+        let ok = |d: Option<NaiveDate>| until.is_none() || d <= until;
+        let r_date = self.rates.peek().map(|r| r.from).filter(|d| ok(*d));
+        let a_date = self
+            .absences
+            .peek()
+            .map(|a| Some(a.date))
+            .filter(|d| ok(*d));
+        match (r_date, a_date) {
+            (Some(r), Some(a)) if r <= a => self.rates.next().map(|r| (r.from, rate_line(r))),
+            (Some(_), None) => self.rates.next().map(|r| (r.from, rate_line(r))),
+            (_, Some(_)) => self
+                .absences
+                .next()
+                .map(|a| (Some(a.date), absence_line(a))),
+            (None, None) => None,
+        }
+    }
+}
+
+fn put_dated<W: Write>(
+    w: &mut W,
+    block: &mut Block,
+    date: Option<NaiveDate>,
+    line: Line,
+) -> fmt::Result {
+    if block.date != date {
+        block.write(w)?;
+        *block = Block::new(date);
+    }
+    block.lines.push(line);
+    Ok(())
+}
+
+fn absence_line(a: &Absence) -> Line {
+    Line::clock(absence_str(a.kind, a.half, a.note.as_deref()), None)
+}
+
+fn rate_line(r: &Rate) -> Line {
+    Line {
+        lead: rate_str(&r.target, r.amount, r.currency.as_deref()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::moment::Duration;
+    #[test]
+    pub fn test_tock_round_trip() {
+        let src = "$fun[play,games]\n1/11/2021\n  work,9:00\n  -12:00\n  _urgent 13:00 -17:00\n2/11/2021\n  play,__,10:00 -11:30\n  22:00 -3/11/2021 2:00\n  23:00 -26:00\n  9:00 \"fixed \\\"login\\\" bug\" -10:00 ~\n  10:30 -11:00\n";
+        let mut a = ClockStore::new();
+        a.read(src, "a").unwrap();
+        let written = write_store(&a, None, OutFormat::Tock).unwrap();
+        let mut b = ClockStore::new();
+        b.read(&written, "b").unwrap();
+        assert_eq!(a.clocks, b.clocks);
+        assert_eq!(
+            b.clocks[b.clocks.len() - 2].note.as_deref(),
+            Some("fixed \"login\" bug")
+        );
+        assert_eq!(a.breaks, b.breaks);
+        assert_eq!(a.breaks[0].duration(), Duration::new(0, 30));
+        assert_eq!(write_store(&b, None, OutFormat::Tock).unwrap(), written);
+
+        //A clockin still waiting for its clockout is kept
+        let rs = a.read("4/11/2021\n  work,_late,18:00\n", "open").unwrap();
+        let written = write_store(&a, rs.curr_in.as_ref(), OutFormat::Tock).unwrap();
+        assert!(written.ends_with("04/11/2021\n  work,_late,18:00\n"));
+        let mut c = ClockStore::new();
+        let rs_c = c.read(&written, "c").unwrap();
+        assert_eq!(rs_c.curr_in.map(|ci| ci.c_in), rs.curr_in.map(|ci| ci.c_in));
+    }
+
+    #[test]
+    pub fn test_quoted_names_round_trip() {
+        let src = "$'big ones'['Acme Corp',acme]\n1/11/2021\n  'Acme Corp',_'needs review',9:00 -12:00\n  'O\\'Brien-web',__'needs review' 13:00 -14:00\n  'Acme Corp'/web/'log in' 15:00 -16:00\n  _ticket=ABC-123,_'due date'='1/12' 16:00 -17:00\n@'Acme Corp' 50 GBP\n3/11/2021\n@$'big ones' 20.5\n  !sick half \"dentist\"\n31/10/2021\n  !holiday\n";
+        let mut a = ClockStore::new();
+        a.read(src, "a").unwrap();
+        assert_eq!(a.groups[0].members[0], "Acme Corp");
+        assert_eq!(a.clocks[0].tags, vec![Tag::new("needs review", None)]);
+        assert_eq!(a.clocks[1].job, "O'Brien-web");
+        assert_eq!(a.clocks[2].job, "Acme Corp/web/log in");
+        assert_eq!(
+            a.clocks[3].tags,
+            vec![
+                Tag::new("ticket", Some("ABC-123")),
+                Tag::new("due date", Some("1/12"))
+            ]
+        );
+        let written = write_store(&a, None, OutFormat::Tock).unwrap();
+        assert!(written.contains("'O\\'Brien-web'"));
+        let mut b = ClockStore::new();
+        b.read(&written, "b").unwrap();
+        assert_eq!(a.clocks, b.clocks);
+        assert_eq!(a.groups, b.groups);
+        assert_eq!(a.rates, b.rates);
+        //Absences are written in date order
+        a.absences.sort_by_key(|a| a.date);
+        assert_eq!(a.absences, b.absences);
+        assert!(written.starts_with("$'big ones'['Acme Corp',acme]\n31/10/2021\n  !holiday\n"));
+    }
+}